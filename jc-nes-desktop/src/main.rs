@@ -1,15 +1,28 @@
 use jc_nes::{Button, Nes, SCREEN_HEIGHT, SCREEN_WIDTH};
-use sdl2::{event::Event, keyboard::Keycode, pixels::PixelFormatEnum};
-use std::{fs::File, io::Read};
+use sdl2::{
+    audio::{AudioQueue, AudioSpecDesired},
+    event::Event,
+    keyboard::Keycode,
+    pixels::PixelFormatEnum,
+};
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
 
-const SYSTEM_HZ: u32 = 320;
+const FPS: u32 = 60;
 const SCREEN_SCALE: f32 = 3.75;
+const SAMPLE_RATE: u32 = 44_100;
+// queued samples (~50 ms) above which emulation waits for the audio device
+const MAX_QUEUED_SAMPLES: u32 = SAMPLE_RATE / 20;
 const TITLE: &str = "Drag and drop the ROM file to play";
 
 fn main() {
     let sdl = sdl2::init().unwrap();
     let video_subsystem = sdl.video().unwrap();
     let mut timer_subsystem = sdl.timer().unwrap();
+    let audio_subsystem = sdl.audio().unwrap();
 
     let window = video_subsystem
         .window(
@@ -36,31 +49,67 @@ fn main() {
         )
         .unwrap();
 
+    let audio_queue: AudioQueue<f32> = audio_subsystem
+        .open_queue(
+            None,
+            &AudioSpecDesired {
+                freq: Some(SAMPLE_RATE as i32),
+                channels: Some(1),
+                samples: None,
+            },
+        )
+        .unwrap();
+    audio_queue.resume();
+
     let mut nes = Nes::new();
     let mut game_loaded = false;
+    let mut jammed = false;
+    let mut save_path: Option<PathBuf> = None;
 
-    let tick_interval = 1000 / SYSTEM_HZ;
+    let frame_interval = 1000 / FPS;
     let mut last_update_time = 0;
     let mut event_pump = sdl.event_pump().unwrap();
     'main: loop {
         while let Some(event) = event_pump.poll_event() {
             match event {
-                Event::Quit { .. } => break 'main,
+                Event::Quit { .. } => {
+                    write_save(&nes, &save_path);
+                    break 'main;
+                }
                 Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } => break 'main,
+                } => {
+                    write_save(&nes, &save_path);
+                    break 'main;
+                }
 
                 Event::DropFile { filename, .. } => {
+                    write_save(&nes, &save_path);
+                    save_path = None;
+
                     let rom = read_file(&filename);
                     nes = Nes::new();
-                    nes.load_rom(&rom);
-                    nes.reset();
-                    game_loaded = true;
-                    canvas
-                        .window_mut()
-                        .set_title(&format!("{} [Currently playing: {}]", TITLE, filename))
-                        .unwrap();
+                    nes.set_sample_rate(SAMPLE_RATE);
+                    jammed = false;
+                    let title = match nes.load_rom(&rom) {
+                        Ok(()) => {
+                            // battery-backed games keep their save next to the ROM
+                            let path = Path::new(&filename).with_extension("sav");
+                            if let Ok(data) = fs::read(&path) {
+                                nes.load_battery_ram(&data);
+                            }
+                            save_path = Some(path);
+                            nes.reset();
+                            game_loaded = true;
+                            format!("{} [Currently playing: {}]", TITLE, filename)
+                        }
+                        Err(error) => {
+                            game_loaded = false;
+                            format!("{} [Could not load {}: {}]", TITLE, filename, error)
+                        }
+                    };
+                    canvas.window_mut().set_title(&title).unwrap();
                     None
                 }
 
@@ -78,20 +127,31 @@ fn main() {
             };
         }
 
-        let current_time = timer_subsystem.ticks();
-        let delta_t = current_time - last_update_time;
-        if game_loaded && tick_interval > delta_t {
-            (0..8).for_each(|_| nes.clock());
+        // the loop runs a bit faster than the console (16 ms frames), so frames
+        // are skipped while the audio device has enough queued to play
+        let queued_samples = audio_queue.size() / std::mem::size_of::<f32>() as u32;
+        if game_loaded && queued_samples < MAX_QUEUED_SAMPLES {
+            nes.run_frame();
+            if nes.jammed() && !jammed {
+                jammed = true;
+                let title = format!("{} [CPU jammed]", canvas.window().title());
+                canvas.window_mut().set_title(&title).unwrap();
+            }
             if let Some(screen) = nes.get_frame() {
-                timer_subsystem.delay(tick_interval - delta_t);
                 texture
                     .update(None, &screen, SCREEN_WIDTH as usize * 3)
                     .unwrap();
                 canvas.copy(&texture, None, None).unwrap();
                 canvas.present();
+                audio_queue.queue_audio(&nes.take_audio_samples()).unwrap();
             }
         }
-        last_update_time = current_time;
+
+        let delta_t = timer_subsystem.ticks() - last_update_time;
+        if frame_interval > delta_t {
+            timer_subsystem.delay(frame_interval - delta_t);
+        }
+        last_update_time = timer_subsystem.ticks();
     }
 }
 
@@ -109,6 +169,14 @@ fn key_to_btn(keycode: Keycode) -> Option<Button> {
     }
 }
 
+fn write_save(nes: &Nes, save_path: &Option<PathBuf>) {
+    if let (Some(path), Some(data)) = (save_path, nes.battery_ram()) {
+        if let Err(error) = fs::write(path, data) {
+            eprintln!("could not write {}: {}", path.display(), error);
+        }
+    }
+}
+
 fn read_file(path: &str) -> Vec<u8> {
     let mut file = File::open(path).unwrap();
     let mut rom = Vec::new();
@@ -9,17 +9,19 @@ let nes = null;
 export const play = async () => {
     const rom = await getROM();
     nes = new Nes();
-    nes.load_rom(rom);
-    nes.reset();
-};
-
-export const clock = () => {
-    if (nes) nes.clock();
+    try {
+        nes.load_rom(rom);
+        nes.reset();
+    } catch (error) {
+        nes = null;
+        window.alert(`Could not load ROM: ${error.message}`);
+    }
 };
 
 export const render = () => {
     if (!nes) return;
 
+    nes.run_frame();
     const frame = nes.get_frame();
     if (!frame) return;
 
@@ -2,18 +2,34 @@ import { default as wasm } from "./jc_nes.js";
 
 import { listROMs } from "./roms.js";
 import { listKeys } from "./keys.js";
-import { play, clock, render, onKeyDown, onKeyUp } from "./handlers.js";
+import { play, render, onKeyDown, onKeyUp } from "./handlers.js";
 
-const FPS = 60;
-const CLOCK_HZ = 10_000_000;
+// milliseconds per frame of an NTSC console (~60.0988 Hz)
+const FRAME_TIME = 1000 / 60.0988;
+const MAX_CATCH_UP = 4 * FRAME_TIME;
 
 (async () => {
     // init wasm module
     await wasm();
 
-    // set clock, video, audio and keyboard handlers
-    window.setInterval(clock, 1000 / CLOCK_HZ);
-    window.setInterval(render, 1000 / FPS);
+    // set video and keyboard handlers, emulated frames run at the NTSC rate
+    // whatever the display refresh rate is
+    let last = null;
+    let elapsed = 0;
+    const loop = (timestamp) => {
+        if (last !== null) {
+            // don't try to catch up after the tab was in the background
+            elapsed = Math.min(elapsed + timestamp - last, MAX_CATCH_UP);
+        }
+        last = timestamp;
+
+        while (elapsed >= FRAME_TIME) {
+            render();
+            elapsed -= FRAME_TIME;
+        }
+        window.requestAnimationFrame(loop);
+    };
+    window.requestAnimationFrame(loop);
     window.onkeydown = onKeyDown;
     window.onkeyup = onKeyUp;
 
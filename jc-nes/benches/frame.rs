@@ -0,0 +1,19 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use jc_nes::Nes;
+
+const ROM: &[u8] = include_bytes!("../../jc-nes-web/site/public/roms/Donkey Kong.nes");
+
+// emulates one full frame of the Donkey Kong title screen
+fn run_frame(c: &mut Criterion) {
+    let mut nes = Nes::new();
+    nes.load_rom(ROM).unwrap();
+    nes.reset();
+    (0..60).for_each(|_| {
+        nes.run_frame();
+    });
+
+    c.bench_function("run_frame", |b| b.iter(|| nes.run_frame()));
+}
+
+criterion_group!(benches, run_frame);
+criterion_main!(benches);
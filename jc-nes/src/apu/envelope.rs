@@ -0,0 +1,58 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// https://wiki.nesdev.com/w/index.php/APU_Envelope
+#[derive(Default)]
+pub struct Envelope {
+    pub(in crate::apu) start: bool,
+    pub(in crate::apu) looping: bool,
+    pub(in crate::apu) constant: bool,
+    pub(in crate::apu) volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.start);
+        state.write_bool(self.looping);
+        state.write_bool(self.constant);
+        state.write_u8(self.volume);
+        state.write_u8(self.divider);
+        state.write_u8(self.decay);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.start = state.read_bool()?;
+        self.looping = state.read_bool()?;
+        self.constant = state.read_bool()?;
+        self.volume = state.read_u8()? & 0x0F;
+        self.divider = state.read_u8()? & 0x0F;
+        self.decay = state.read_u8()? & 0x0F;
+        Ok(())
+    }
+}
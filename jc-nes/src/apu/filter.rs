@@ -0,0 +1,54 @@
+use std::f32::consts::PI;
+
+// https://wiki.nesdev.com/w/index.php/APU_Mixer
+// first-order RC filters applied to the mixed output at the host sample rate
+pub enum Filter {
+    HighPass {
+        alpha: f32,
+        prev_in: f32,
+        prev_out: f32,
+    },
+    LowPass {
+        alpha: f32,
+        prev_out: f32,
+    },
+}
+
+impl Filter {
+    pub fn high_pass(sample_rate: u32, cutoff: f32) -> Filter {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate as f32;
+        Filter::HighPass {
+            alpha: rc / (rc + dt),
+            prev_in: 0.0,
+            prev_out: 0.0,
+        }
+    }
+
+    pub fn low_pass(sample_rate: u32, cutoff: f32) -> Filter {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate as f32;
+        Filter::LowPass {
+            alpha: dt / (rc + dt),
+            prev_out: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        match self {
+            Filter::HighPass {
+                alpha,
+                prev_in,
+                prev_out,
+            } => {
+                *prev_out = *alpha * (*prev_out + sample - *prev_in);
+                *prev_in = sample;
+                *prev_out
+            }
+            Filter::LowPass { alpha, prev_out } => {
+                *prev_out += *alpha * (sample - *prev_out);
+                *prev_out
+            }
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// https://wiki.nesdev.com/w/index.php/APU_Frame_Counter
+#[derive(Default)]
+pub struct FrameCounter {
+    pub(in crate::apu) irq: bool,
+    five_step: bool,
+    irq_inhibit: bool,
+    cycle: u32,
+}
+
+#[derive(Default)]
+pub struct FrameClock {
+    pub(in crate::apu) quarter: bool,
+    pub(in crate::apu) half: bool,
+}
+
+impl FrameCounter {
+    /// Clocked once per CPU cycle, reports which frame units should be clocked
+    pub fn clock(&mut self) -> FrameClock {
+        self.cycle += 1;
+        match (self.five_step, self.cycle) {
+            (_, 7457) | (_, 22371) => FrameClock {
+                quarter: true,
+                half: false,
+            },
+            (_, 14913) | (true, 37281) => FrameClock {
+                quarter: true,
+                half: true,
+            },
+            (false, 29829) => {
+                self.raise_irq();
+                FrameClock {
+                    quarter: true,
+                    half: true,
+                }
+            }
+            (false, 29830) => {
+                self.raise_irq();
+                self.cycle = 0;
+                FrameClock::default()
+            }
+            (true, 37282) => {
+                self.cycle = 0;
+                FrameClock::default()
+            }
+            _ => FrameClock::default(),
+        }
+    }
+
+    /// Writes to $4017, in 5-step mode all units are clocked immediately
+    pub fn write(&mut self, data: u8) -> FrameClock {
+        self.five_step = data & 0x80 != 0;
+        self.irq_inhibit = data & 0x40 != 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+        self.cycle = 0;
+
+        FrameClock {
+            quarter: self.five_step,
+            half: self.five_step,
+        }
+    }
+
+    fn raise_irq(&mut self) {
+        if !self.irq_inhibit {
+            self.irq = true;
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.irq);
+        state.write_bool(self.five_step);
+        state.write_bool(self.irq_inhibit);
+        state.write_u32(self.cycle);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.irq = state.read_bool()?;
+        self.five_step = state.read_bool()?;
+        self.irq_inhibit = state.read_bool()?;
+        self.cycle = state.read_u32()?;
+        // past the end of the sequence it would never wrap around
+        if self.cycle > 37282 {
+            return Err(StateError::Corrupted);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// https://wiki.nesdev.com/w/index.php/APU_Length_Counter
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+#[derive(Default)]
+pub struct LengthCounter {
+    pub(in crate::apu) halt: bool,
+    enabled: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[index as usize];
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.counter > 0
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.halt);
+        state.write_bool(self.enabled);
+        state.write_u8(self.counter);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.halt = state.read_bool()?;
+        self.enabled = state.read_bool()?;
+        self.counter = state.read_u8()?;
+        Ok(())
+    }
+}
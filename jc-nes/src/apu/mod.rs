@@ -0,0 +1,252 @@
+mod dmc;
+pub(crate) mod envelope;
+mod filter;
+mod frame_counter;
+pub(crate) mod length_counter;
+mod noise;
+pub(crate) mod pulse;
+mod sweep;
+mod triangle;
+
+use crate::apu::dmc::Dmc;
+use crate::apu::filter::Filter;
+use crate::apu::frame_counter::{FrameClock, FrameCounter};
+use crate::apu::noise::Noise;
+use crate::apu::pulse::Pulse;
+use crate::apu::triangle::Triangle;
+use crate::bus::Device;
+use crate::state::{StateError, StateReader, StateWriter};
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+const CPU_FREQUENCY: f64 = 1_789_773.0;
+
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    cycle: usize,
+
+    // level of the cartridge expansion audio, if any
+    expansion_output: f32,
+
+    // https://wiki.nesdev.com/w/index.php/APU_Mixer#Lookup_Table
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+
+    // output samples at the host sample rate
+    sample_rate: u32,
+    cycles_per_sample: f64,
+    sample_timer: f64,
+    sample_sum: f32,
+    sample_count: u32,
+    filters: [Filter; 3],
+    samples: Vec<f32>,
+}
+
+impl Apu {
+    pub fn new(sample_rate: u32) -> Apu {
+        let mut pulse_table = [0f32; 31];
+        for (n, value) in pulse_table.iter_mut().enumerate().skip(1) {
+            *value = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+
+        let mut tnd_table = [0f32; 203];
+        for (n, value) in tnd_table.iter_mut().enumerate().skip(1) {
+            *value = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+
+        Apu {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            frame_counter: FrameCounter::default(),
+            cycle: 0,
+            expansion_output: 0.0,
+            pulse_table,
+            tnd_table,
+            sample_rate,
+            cycles_per_sample: CPU_FREQUENCY / sample_rate as f64,
+            sample_timer: 0.0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            filters: Apu::filters(sample_rate),
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn clock(&mut self) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.cycle % 2 == 1 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+
+        let frame_clock = self.frame_counter.clock();
+        self.clock_frame_units(frame_clock);
+
+        self.sample();
+        self.cycle += 1;
+    }
+
+    pub fn reset(&mut self) {
+        self.write(0x15, 0x00);
+        self.frame_counter.irq = false;
+        self.dmc.irq = false;
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_counter.irq || self.dmc.irq
+    }
+
+    /// Address the DMC wants to read its next sample byte from, if any
+    pub fn dmc_request(&self) -> Option<u16> {
+        self.dmc.request()
+    }
+
+    pub fn dmc_fill(&mut self, data: u8) {
+        self.dmc.fill(data);
+    }
+
+    pub fn set_expansion_output(&mut self, output: f32) {
+        self.expansion_output = output;
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.cycles_per_sample = CPU_FREQUENCY / sample_rate as f64;
+        self.filters = Apu::filters(sample_rate);
+        self.samples.clear();
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+}
+
+impl Apu {
+    fn filters(sample_rate: u32) -> [Filter; 3] {
+        [
+            Filter::high_pass(sample_rate, 90.0),
+            Filter::high_pass(sample_rate, 440.0),
+            Filter::low_pass(sample_rate, 14_000.0),
+        ]
+    }
+
+    fn clock_frame_units(&mut self, frame_clock: FrameClock) {
+        if frame_clock.quarter {
+            self.pulse1.envelope.clock();
+            self.pulse2.envelope.clock();
+            self.noise.envelope.clock();
+            self.triangle.clock_linear_counter();
+        }
+
+        if frame_clock.half {
+            self.pulse1.length_counter.clock();
+            self.pulse2.length_counter.clock();
+            self.triangle.length_counter.clock();
+            self.noise.length_counter.clock();
+            self.pulse1.clock_sweep();
+            self.pulse2.clock_sweep();
+        }
+    }
+
+    fn mix(&self) -> f32 {
+        let pulse = self.pulse1.output() + self.pulse2.output();
+        let tnd = 3 * self.triangle.output() + 2 * self.noise.output() + self.dmc.output();
+        self.pulse_table[pulse as usize] + self.tnd_table[tnd as usize] + self.expansion_output
+    }
+
+    // averages the mixer output over each host sample period
+    fn sample(&mut self) {
+        self.sample_sum += self.mix();
+        self.sample_count += 1;
+        self.sample_timer += 1.0;
+
+        if self.sample_timer >= self.cycles_per_sample {
+            self.sample_timer -= self.cycles_per_sample;
+
+            let mut sample = self.sample_sum / self.sample_count as f32;
+            for filter in self.filters.iter_mut() {
+                sample = filter.process(sample);
+            }
+            self.sample_sum = 0.0;
+            self.sample_count = 0;
+
+            // keep at most one second of audio if the host is not consuming it
+            if self.samples.len() < self.sample_rate as usize {
+                self.samples.push(sample);
+            }
+        }
+    }
+}
+
+// This interface is exposed for the APU registers ($4000-$4013, $4015 and $4017 on CPU Bus)
+impl Device for Apu {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x15 => {
+                let data = self.pulse1.length_counter.active() as u8
+                    | (self.pulse2.length_counter.active() as u8) << 1
+                    | (self.triangle.length_counter.active() as u8) << 2
+                    | (self.noise.length_counter.active() as u8) << 3
+                    | (self.dmc.active() as u8) << 4
+                    | (self.frame_counter.irq as u8) << 6
+                    | (self.dmc.irq as u8) << 7;
+                self.frame_counter.irq = false;
+                data
+            }
+            _ => 0x00,
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x00..=0x03 => self.pulse1.write(address, data),
+            0x04..=0x07 => self.pulse2.write(address - 0x04, data),
+            0x08..=0x0B => self.triangle.write(address - 0x08, data),
+            0x0C..=0x0F => self.noise.write(address - 0x0C, data),
+            0x10..=0x13 => self.dmc.write(address - 0x10, data),
+            0x15 => {
+                self.pulse1.length_counter.set_enabled(data & 0x01 != 0);
+                self.pulse2.length_counter.set_enabled(data & 0x02 != 0);
+                self.triangle.length_counter.set_enabled(data & 0x04 != 0);
+                self.noise.length_counter.set_enabled(data & 0x08 != 0);
+                self.dmc.set_enabled(data & 0x10 != 0);
+            }
+            0x17 => {
+                let frame_clock = self.frame_counter.write(data);
+                self.clock_frame_units(frame_clock);
+            }
+            _ => (),
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        self.triangle.save_state(state);
+        self.noise.save_state(state);
+        self.dmc.save_state(state);
+        self.frame_counter.save_state(state);
+        state.write_usize(self.cycle);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pulse1.load_state(state)?;
+        self.pulse2.load_state(state)?;
+        self.triangle.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.dmc.load_state(state)?;
+        self.frame_counter.load_state(state)?;
+        self.cycle = state.read_usize()?;
+        Ok(())
+    }
+}
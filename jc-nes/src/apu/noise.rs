@@ -0,0 +1,95 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// https://wiki.nesdev.com/w/index.php/APU_Noise
+// periods in CPU cycles (NTSC)
+const PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+pub struct Noise {
+    pub(in crate::apu) envelope: Envelope,
+    pub(in crate::apu) length_counter: LengthCounter,
+    mode: bool,
+    shift_register: u16,
+    timer: u16,
+    timer_period: u16,
+}
+
+impl Default for Noise {
+    fn default() -> Noise {
+        Noise {
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+            mode: false,
+            shift_register: 0x0001,
+            timer: 0,
+            timer_period: PERIODS[0],
+        }
+    }
+}
+
+impl Noise {
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0x00 => {
+                self.length_counter.halt = data & 0x20 != 0;
+                self.envelope.looping = data & 0x20 != 0;
+                self.envelope.constant = data & 0x10 != 0;
+                self.envelope.volume = data & 0x0F;
+            }
+            0x02 => {
+                self.mode = data & 0x80 != 0;
+                self.timer_period = PERIODS[(data & 0x0F) as usize];
+            }
+            0x03 => {
+                self.length_counter.load(data >> 3);
+                self.envelope.start = true;
+            }
+            _ => (),
+        }
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift_register & 0x01) ^ ((self.shift_register >> tap) & 0x01);
+            self.shift_register >>= 1;
+            self.shift_register |= feedback << 14;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 0x01 == 1 || !self.length_counter.active() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.envelope.save_state(state);
+        self.length_counter.save_state(state);
+        state.write_bool(self.mode);
+        state.write_u16(self.shift_register);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.envelope.load_state(state)?;
+        self.length_counter.load_state(state)?;
+        self.mode = state.read_bool()?;
+        self.shift_register = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        self.timer_period = state.read_u16()?;
+        if self.timer_period == 0 {
+            return Err(StateError::Corrupted);
+        }
+        Ok(())
+    }
+}
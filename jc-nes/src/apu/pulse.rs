@@ -0,0 +1,127 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::apu::sweep::Sweep;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// https://wiki.nesdev.com/w/index.php/APU_Pulse
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+pub struct Pulse {
+    pub(crate) envelope: Envelope,
+    pub(crate) length_counter: LengthCounter,
+    // MMC5 pulses have no sweep unit, so they are never muted by it
+    sweep: Option<Sweep>,
+    duty: u8,
+    step: u8,
+    timer: u16,
+    timer_period: u16,
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Pulse {
+        Pulse {
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+            sweep: Some(Sweep::new(ones_complement)),
+            duty: 0,
+            step: 0,
+            timer: 0,
+            timer_period: 0,
+        }
+    }
+
+    pub fn without_sweep() -> Pulse {
+        Pulse {
+            sweep: None,
+            ..Pulse::new(false)
+        }
+    }
+
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0x00 => {
+                self.duty = data >> 6;
+                self.length_counter.halt = data & 0x20 != 0;
+                self.envelope.looping = data & 0x20 != 0;
+                self.envelope.constant = data & 0x10 != 0;
+                self.envelope.volume = data & 0x0F;
+            }
+            0x01 => {
+                if let Some(sweep) = &mut self.sweep {
+                    sweep.enabled = data & 0x80 != 0;
+                    sweep.period = (data >> 4) & 0x07;
+                    sweep.negate = data & 0x08 != 0;
+                    sweep.shift = data & 0x07;
+                    sweep.reload = true;
+                }
+            }
+            0x02 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            0x03 => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                self.length_counter.load(data >> 3);
+                self.step = 0;
+                self.envelope.start = true;
+            }
+            _ => (),
+        }
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if let Some(sweep) = &mut self.sweep {
+            sweep.clock(&mut self.timer_period);
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.active()
+            || DUTY_TABLE[self.duty as usize][self.step as usize] == 0
+            || self
+                .sweep
+                .as_ref()
+                .is_some_and(|sweep| sweep.muting(self.timer_period))
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.envelope.save_state(state);
+        self.length_counter.save_state(state);
+        if let Some(sweep) = &self.sweep {
+            sweep.save_state(state);
+        }
+        state.write_u8(self.duty);
+        state.write_u8(self.step);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.envelope.load_state(state)?;
+        self.length_counter.load_state(state)?;
+        if let Some(sweep) = &mut self.sweep {
+            sweep.load_state(state)?;
+        }
+        self.duty = state.read_u8()? & 0x03;
+        self.step = state.read_u8()? & 0x07;
+        self.timer = state.read_u16()? & 0x07FF;
+        self.timer_period = state.read_u16()? & 0x07FF;
+        Ok(())
+    }
+}
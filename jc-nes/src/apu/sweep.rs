@@ -0,0 +1,69 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// https://wiki.nesdev.com/w/index.php/APU_Sweep
+#[derive(Default)]
+pub struct Sweep {
+    pub(in crate::apu) enabled: bool,
+    pub(in crate::apu) period: u8,
+    pub(in crate::apu) negate: bool,
+    pub(in crate::apu) shift: u8,
+    pub(in crate::apu) reload: bool,
+    divider: u8,
+
+    // pulse 1 negates with one's complement, pulse 2 with two's complement
+    ones_complement: bool,
+}
+
+impl Sweep {
+    pub fn new(ones_complement: bool) -> Sweep {
+        Sweep {
+            ones_complement,
+            ..Default::default()
+        }
+    }
+
+    pub fn clock(&mut self, timer_period: &mut u16) {
+        if self.divider == 0 && self.enabled && self.shift > 0 && !self.muting(*timer_period) {
+            *timer_period = self.target(*timer_period);
+        }
+
+        if self.divider == 0 || self.reload {
+            self.divider = self.period;
+            self.reload = false;
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn muting(&self, timer_period: u16) -> bool {
+        timer_period < 8 || self.target(timer_period) > 0x07FF
+    }
+
+    fn target(&self, timer_period: u16) -> u16 {
+        let change = timer_period >> self.shift;
+        if self.negate {
+            timer_period.saturating_sub(change + self.ones_complement as u16)
+        } else {
+            timer_period + change
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.period);
+        state.write_bool(self.negate);
+        state.write_u8(self.shift);
+        state.write_bool(self.reload);
+        state.write_u8(self.divider);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.period = state.read_u8()? & 0x07;
+        self.negate = state.read_bool()?;
+        self.shift = state.read_u8()? & 0x07;
+        self.reload = state.read_bool()?;
+        self.divider = state.read_u8()? & 0x07;
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use crate::apu::length_counter::LengthCounter;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// https://wiki.nesdev.com/w/index.php/APU_Triangle
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+#[derive(Default)]
+pub struct Triangle {
+    pub(in crate::apu) length_counter: LengthCounter,
+    control: bool,
+    linear_counter: u8,
+    linear_counter_period: u8,
+    linear_counter_reload: bool,
+    step: u8,
+    timer: u16,
+    timer_period: u16,
+}
+
+impl Triangle {
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0x00 => {
+                self.control = data & 0x80 != 0;
+                self.length_counter.halt = data & 0x80 != 0;
+                self.linear_counter_period = data & 0x7F;
+            }
+            0x02 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            0x03 => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                self.length_counter.load(data >> 3);
+                self.linear_counter_reload = true;
+            }
+            _ => (),
+        }
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length_counter.active() && self.linear_counter > 0 {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_linear_counter(&mut self) {
+        if self.linear_counter_reload {
+            self.linear_counter = self.linear_counter_period;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_counter_reload = false;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.step as usize]
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.length_counter.save_state(state);
+        state.write_bool(self.control);
+        state.write_u8(self.linear_counter);
+        state.write_u8(self.linear_counter_period);
+        state.write_bool(self.linear_counter_reload);
+        state.write_u8(self.step);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.length_counter.load_state(state)?;
+        self.control = state.read_bool()?;
+        self.linear_counter = state.read_u8()? & 0x7F;
+        self.linear_counter_period = state.read_u8()? & 0x7F;
+        self.linear_counter_reload = state.read_bool()?;
+        self.step = state.read_u8()? & 0x1F;
+        self.timer = state.read_u16()? & 0x07FF;
+        self.timer_period = state.read_u16()? & 0x07FF;
+        Ok(())
+    }
+}
@@ -1,20 +1,66 @@
+use crate::state::{StateError, StateReader, StateWriter};
 use std::cell::RefCell;
 use std::ops::RangeInclusive;
 use std::rc::Rc;
 
-#[derive(Default)]
+const PAGE_SIZE: usize = 0x100;
+const PAGES: usize = 0x10000 / PAGE_SIZE;
+
+// Every 256-byte page caches the mirror and device that cover all of its
+// addresses, pages with more than one only fall back to scanning the lists
+#[derive(Clone, Copy)]
+enum Page<T> {
+    Uniform(T),
+    Split,
+}
+
 pub struct Bus {
     devices: Vec<(RangeInclusive<u16>, Box<dyn Device>)>,
     mirrors: Vec<(RangeInclusive<u16>, u16)>,
+    device_pages: [Page<Option<usize>>; PAGES],
+    mirror_pages: [Page<Option<u16>>; PAGES],
 }
 
 pub trait Device {
     fn read(&mut self, address: u16) -> u8;
     fn write(&mut self, address: u16, data: u8);
+
+    // PPU reads while rendering, which some mappers (MMC5) bank differently
+    // from accesses through $2007
+    fn fetch(&mut self, address: u16, _fetch: Fetch) -> u8 {
+        self.read(address)
+    }
+
+    // devices with internal state (de)serialize it for save states
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
 }
 
 pub type SharedMut<T> = Rc<RefCell<T>>;
 
+/// What the PPU is fetching while rendering
+#[derive(Clone, Copy, Debug)]
+pub struct Fetch {
+    /// Sprite patterns, as opposed to background nametable/pattern fetches
+    pub sprite: bool,
+    /// 8x16 sprites, whose patterns can be banked apart from the background
+    pub tall_sprites: bool,
+}
+
+impl Default for Bus {
+    fn default() -> Bus {
+        Bus {
+            devices: Vec::new(),
+            mirrors: Vec::new(),
+            device_pages: [Page::Uniform(None); PAGES],
+            mirror_pages: [Page::Uniform(None); PAGES],
+        }
+    }
+}
+
 impl Bus {
     pub fn connect(
         &mut self,
@@ -22,45 +68,95 @@ impl Bus {
         device: impl Device + 'static,
     ) {
         self.devices.push((addressable_range, Box::new(device)));
+        self.device_pages = Bus::pages(|address| self.find_device(address));
     }
 
     pub fn add_mirror(&mut self, addressable_range: RangeInclusive<u16>, max: u16) {
         self.mirrors.push((addressable_range, max));
+        self.mirror_pages = Bus::pages(|address| self.find_mirror(address));
     }
 
-    fn mirror(&self, address: u16) -> u16 {
-        if let Some((_, max)) = self
-            .mirrors
+    fn pages<T: Copy + PartialEq>(lookup: impl Fn(u16) -> T) -> [Page<T>; PAGES] {
+        let mut pages = [Page::Split; PAGES];
+        for (page, entry) in pages.iter_mut().enumerate() {
+            let start = (page * PAGE_SIZE) as u16;
+            let first = lookup(start);
+            if (start..=start + (PAGE_SIZE - 1) as u16).all(|address| lookup(address) == first) {
+                *entry = Page::Uniform(first);
+            }
+        }
+        pages
+    }
+
+    fn find_device(&self, address: u16) -> Option<usize> {
+        self.devices
+            .iter()
+            .position(|(addressable_range, _)| addressable_range.contains(&address))
+    }
+
+    fn find_mirror(&self, address: u16) -> Option<u16> {
+        self.mirrors
             .iter()
             .find(|(addressable_range, _)| addressable_range.contains(&address))
-        {
-            address & max
-        } else {
-            address
+            .map(|(_, max)| *max)
+    }
+
+    fn mirror(&self, address: u16) -> u16 {
+        let max = match self.mirror_pages[address as usize / PAGE_SIZE] {
+            Page::Uniform(max) => max,
+            Page::Split => self.find_mirror(address),
+        };
+        match max {
+            Some(max) => address & max,
+            None => address,
         }
     }
+
+    fn device(&mut self, address: u16) -> Option<&mut (RangeInclusive<u16>, Box<dyn Device>)> {
+        let index = match self.device_pages[address as usize / PAGE_SIZE] {
+            Page::Uniform(index) => index,
+            Page::Split => self.find_device(address),
+        };
+        index.map(move |index| &mut self.devices[index])
+    }
 }
 
 impl Device for Bus {
     fn read(&mut self, address: u16) -> u8 {
         let address = self.mirror(address);
-        self.devices
-            .iter_mut()
-            .find(|(addressable_range, _)| addressable_range.contains(&address))
-            .map(|(range, device)| device.read(address - range.start()))
+        self.device(address)
+            .map(|(range, device)| device.read(address - *range.start()))
             .unwrap_or_else(|| panic!("no device to read from at address 0x{:04X}", address))
     }
 
     fn write(&mut self, address: u16, data: u8) {
         let address = self.mirror(address);
+        self.device(address)
+            .map(|(range, device)| device.write(address - *range.start(), data))
+            .unwrap_or_else(|| panic!("no device to write to at address 0x{:04X}", address))
+    }
+
+    fn fetch(&mut self, address: u16, fetch: Fetch) -> u8 {
+        let address = self.mirror(address);
+        self.device(address)
+            .map(|(range, device)| device.fetch(address - *range.start(), fetch))
+            .unwrap_or_else(|| panic!("no device to fetch from at address 0x{:04X}", address))
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.devices
+            .iter()
+            .for_each(|(_, device)| device.save_state(state));
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
         self.devices
             .iter_mut()
-            .find(|(addressable_range, _)| addressable_range.contains(&address))
-            .map(|(range, device)| device.write(address - range.start(), data))
-            .unwrap_or_else(|| panic!("no device to write to at address 0x{:04X}", address))
+            .try_for_each(|(_, device)| device.load_state(state))
     }
 }
 
+// shared devices are saved by their owner, not by every bus they are connected to
 impl<T: Device> Device for SharedMut<T> {
     fn read(&mut self, address: u16) -> u8 {
         self.borrow_mut().read(address)
@@ -69,4 +165,8 @@ impl<T: Device> Device for SharedMut<T> {
     fn write(&mut self, address: u16, data: u8) {
         self.borrow_mut().write(address, data);
     }
+
+    fn fetch(&mut self, address: u16, fetch: Fetch) -> u8 {
+        self.borrow_mut().fetch(address, fetch)
+    }
 }
@@ -0,0 +1,66 @@
+use std::error::Error;
+use std::fmt;
+
+#[cfg(feature = "web")]
+use wasm_bindgen::prelude::*;
+
+/// Reasons a ROM image can be rejected by `Nes::load_rom`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    /// The file does not start with the "NES\x1A" signature
+    BadMagic,
+    /// The file is shorter than the 16-byte header (plus trainer, if present)
+    TruncatedHeader,
+    /// The file ends before the PRG-ROM size declared in the header
+    TruncatedPrgRom {
+        expected: usize,
+        found: usize,
+    },
+    /// The file ends before the CHR-ROM size declared in the header
+    TruncatedChrRom {
+        expected: usize,
+        found: usize,
+    },
+    UnsupportedMapper {
+        mapper_id: u16,
+        submapper_id: u8,
+    },
+    UnsupportedFormat(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::BadMagic => write!(f, "not a .NES file (header signature not correct)"),
+            LoadError::TruncatedHeader => write!(f, "file is too short to hold a .NES header"),
+            LoadError::TruncatedPrgRom { expected, found } => write!(
+                f,
+                "PRG-ROM is truncated (expected {} bytes, found {})",
+                expected, found
+            ),
+            LoadError::TruncatedChrRom { expected, found } => write!(
+                f,
+                "CHR-ROM is truncated (expected {} bytes, found {})",
+                expected, found
+            ),
+            LoadError::UnsupportedMapper {
+                mapper_id,
+                submapper_id,
+            } => write!(
+                f,
+                "unsupported mapper {} (submapper {})",
+                mapper_id, submapper_id
+            ),
+            LoadError::UnsupportedFormat(reason) => write!(f, "unsupported format: {}", reason),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+#[cfg(feature = "web")]
+impl From<LoadError> for JsValue {
+    fn from(error: LoadError) -> JsValue {
+        JsError::new(&error.to_string()).into()
+    }
+}
@@ -0,0 +1,172 @@
+use crate::cartridge::error::LoadError;
+use crate::cartridge::MirrorMode;
+use std::convert::TryFrom;
+
+// https://wiki.nesdev.com/w/index.php/INES
+// https://wiki.nesdev.com/w/index.php/NES_2.0
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RomFormat {
+    INes,
+    Nes20,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    /// Extended console type from byte 13 of a NES 2.0 header
+    Extended(u8),
+}
+
+/// Metadata parsed from the 16-byte header of an iNES or NES 2.0 file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CartridgeInfo {
+    pub format: RomFormat,
+    pub mapper_id: u16,
+    pub submapper_id: u8,
+    pub mirror: MirrorMode,
+    pub battery: bool,
+    pub trainer: bool,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+    pub misc_roms: u8,
+    pub expansion_device: u8,
+}
+
+/// Parses the header at the start of a .NES file, anything after its 16 bytes
+/// is ignored
+impl TryFrom<&[u8]> for CartridgeInfo {
+    type Error = LoadError;
+
+    fn try_from(header: &[u8]) -> Result<CartridgeInfo, LoadError> {
+        match header.get(0..4) {
+            Some([0x4E, 0x45, 0x53, 0x1A]) => (),
+            _ => return Err(LoadError::BadMagic),
+        }
+        if header.len() < 16 {
+            return Err(LoadError::TruncatedHeader);
+        }
+
+        let flag6 = header[6];
+        let flag7 = header[7];
+
+        let format = if flag7 & 0x0C == 0x08 {
+            RomFormat::Nes20
+        } else {
+            RomFormat::INes
+        };
+
+        // four-screen boards carry their own extra nametable RAM
+        let mirror = if flag6 & 0x08 != 0 {
+            MirrorMode::FourScreen
+        } else if flag6 & 0x01 == 1 {
+            MirrorMode::Vertical
+        } else {
+            MirrorMode::Horizontal
+        };
+
+        let console_type = match flag7 & 0x03 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(header[13] & 0x0F),
+        };
+
+        let mut info = CartridgeInfo {
+            format,
+            mapper_id: ((flag7 & 0xF0) | (flag6 >> 4)) as u16,
+            submapper_id: 0,
+            mirror,
+            battery: flag6 & 0x02 != 0,
+            trainer: flag6 & 0x04 != 0,
+            prg_rom_size: header[4] as usize * 16 * 1024,
+            chr_rom_size: header[5] as usize * 8 * 1024,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            timing: Timing::Ntsc,
+            console_type,
+            misc_roms: 0,
+            expansion_device: 0,
+        };
+
+        match format {
+            RomFormat::Nes20 => {
+                info.mapper_id |= ((header[8] & 0x0F) as u16) << 8;
+                info.submapper_id = header[8] >> 4;
+                info.prg_rom_size = rom_size(header[4], header[9] & 0x0F, 16 * 1024);
+                info.chr_rom_size = rom_size(header[5], header[9] >> 4, 8 * 1024);
+                info.prg_ram_size = ram_size(header[10] & 0x0F);
+                info.prg_nvram_size = ram_size(header[10] >> 4);
+                info.chr_ram_size = ram_size(header[11] & 0x0F);
+                info.chr_nvram_size = ram_size(header[11] >> 4);
+                info.timing = match header[12] & 0x03 {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Dendy,
+                };
+                info.misc_roms = header[14] & 0x03;
+                info.expansion_device = header[15] & 0x3F;
+            }
+            RomFormat::INes => {
+                // old dumping tools wrote garbage ("DiskDude!") over bytes 7-15
+                if header[12..16].iter().any(|&byte| byte != 0) {
+                    info.mapper_id &= 0x0F;
+                    info.console_type = ConsoleType::Nes;
+                }
+
+                // a zero PRG-RAM size means 8 KiB for compatibility
+                let prg_ram_size = header[8].max(1) as usize * 8 * 1024;
+                if info.battery {
+                    info.prg_nvram_size = prg_ram_size;
+                } else {
+                    info.prg_ram_size = prg_ram_size;
+                }
+                if info.chr_rom_size == 0 {
+                    info.chr_ram_size = 8 * 1024;
+                }
+                if header[9] & 0x01 != 0 {
+                    info.timing = Timing::Pal;
+                }
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+// sizes with an MSB nibble of $F use the exponent-multiplier notation
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        (((msb as usize) << 8) | lsb as usize) * unit
+    }
+}
+
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
@@ -1,23 +1,28 @@
-use crate::bus::Device;
+use crate::bus::{Device, SharedMut};
 use crate::cartridge::Cartridge;
+use crate::state::{StateError, StateReader, StateWriter};
 
 pub struct PrgMapper {
     prg_banks: usize,
     prg_mem: Vec<u8>,
+    prg_ram: SharedMut<Vec<u8>>,
 }
 
 pub struct ChrMapper {
     chr_mem: Vec<u8>,
+    chr_ram: bool,
 }
 
 pub fn new_mapper(cartridge: Cartridge) -> (PrgMapper, ChrMapper) {
     let prg_mapper = PrgMapper {
         prg_mem: cartridge.prg_rom,
         prg_banks: cartridge.prg_banks,
+        prg_ram: cartridge.prg_ram,
     };
     let chr_mapper = ChrMapper {
+        chr_ram: cartridge.chr_banks == 0,
         chr_mem: if cartridge.chr_banks == 0 {
-            [0u8; 8 * 1024].to_vec()
+            vec![0u8; cartridge.chr_ram_size]
         } else {
             cartridge.chr_rom
         },
@@ -35,19 +40,53 @@ impl PrgMapper {
     }
 }
 
+// This interface is exposed for PRG-RAM ($6000-$7FFF) and PRG-ROM ($8000-$FFFF)
 impl Device for PrgMapper {
     fn read(&mut self, address: u16) -> u8 {
-        let address = self.map_address(address);
-        self.prg_mem[address as usize]
+        match address {
+            0x0000..=0x1FFF => self.prg_ram.borrow()[address as usize],
+            _ => self.prg_mem[self.map_address(address - 0x2000) as usize],
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if address < 0x2000 {
+            self.prg_ram.borrow_mut()[address as usize] = data;
+        }
     }
 
-    fn write(&mut self, _address: u16, _data: u8) {}
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram.borrow());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.prg_ram.borrow_mut())
+    }
 }
 
 impl Device for ChrMapper {
     fn read(&mut self, address: u16) -> u8 {
-        self.chr_mem[address as usize]
+        // less than 8 KiB of CHR-RAM is mirrored
+        self.chr_mem[address as usize % self.chr_mem.len()]
     }
 
-    fn write(&mut self, _address: u16, _data: u8) {}
+    fn write(&mut self, address: u16, data: u8) {
+        if self.chr_ram {
+            let len = self.chr_mem.len();
+            self.chr_mem[address as usize % len] = data;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        if self.chr_ram {
+            state.write_bytes(&self.chr_mem);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if self.chr_ram {
+            state.read_bytes_into(&mut self.chr_mem)?;
+        }
+        Ok(())
+    }
 }
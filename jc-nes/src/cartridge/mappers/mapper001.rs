@@ -0,0 +1,252 @@
+use crate::bus::{Device, SharedMut};
+use crate::cartridge::mappers::Expansion;
+use crate::cartridge::{Cartridge, MirrorMode};
+use crate::state::{StateError, StateReader, StateWriter};
+use std::{cell::RefCell, rc::Rc};
+
+// https://wiki.nesdev.com/w/index.php/MMC1
+pub struct Registers {
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_bank: u8,
+    // the serial port ignores a write on the cycle right after another one,
+    // such as the second write of a read-modify-write instruction
+    cycles_since_write: u8,
+}
+
+pub struct PrgMapper {
+    registers: SharedMut<Registers>,
+    mirror_mode: SharedMut<MirrorMode>,
+    shift_register: u8,
+    shift_count: u8,
+    prg_mem: Vec<u8>,
+    prg_banks: usize,
+    prg_ram: SharedMut<Vec<u8>>,
+}
+
+pub struct ChrMapper {
+    registers: SharedMut<Registers>,
+    chr_mem: Vec<u8>,
+    chr_ram: bool,
+}
+
+pub fn new_mapper(
+    cartridge: Cartridge,
+    mirror_mode: SharedMut<MirrorMode>,
+) -> (PrgMapper, ChrMapper, SharedMut<Registers>) {
+    // PRG mode 3 on power-up, last bank fixed at $C000
+    let registers = Rc::new(RefCell::new(Registers {
+        control: 0x0C,
+        chr_bank0: 0x00,
+        chr_bank1: 0x00,
+        prg_bank: 0x00,
+        cycles_since_write: u8::MAX,
+    }));
+
+    let prg_mapper = PrgMapper {
+        registers: registers.clone(),
+        mirror_mode,
+        shift_register: 0x00,
+        shift_count: 0,
+        prg_mem: cartridge.prg_rom,
+        prg_banks: cartridge.prg_banks,
+        prg_ram: cartridge.prg_ram,
+    };
+    let chr_mapper = ChrMapper {
+        registers: registers.clone(),
+        chr_ram: cartridge.chr_banks == 0,
+        chr_mem: if cartridge.chr_banks == 0 {
+            vec![0u8; cartridge.chr_ram_size]
+        } else {
+            cartridge.chr_rom
+        },
+    };
+    (prg_mapper, chr_mapper, registers)
+}
+
+impl PrgMapper {
+    fn map_address(&self, address: u16) -> usize {
+        let registers = self.registers.borrow();
+
+        // 512 KiB boards (SUROM) select the outer 256 KiB with CHR bank 0
+        let outer = if self.prg_banks > 16 {
+            (registers.chr_bank0 & 0x10) as usize
+        } else {
+            0
+        };
+        let bank = (registers.prg_bank & 0x0F) as usize;
+
+        let bank = match (registers.control >> 2) & 0x03 {
+            // switch 32 KiB at $8000
+            0 | 1 => (bank & 0x0E) | (address >= 0x4000) as usize,
+            // fix first bank at $8000, switch 16 KiB at $C000
+            2 => {
+                if address < 0x4000 {
+                    0
+                } else {
+                    bank
+                }
+            }
+            // fix last bank at $C000, switch 16 KiB at $8000
+            _ => {
+                if address < 0x4000 {
+                    bank
+                } else {
+                    0x0F
+                }
+            }
+        };
+
+        ((outer | bank) % self.prg_banks) * 0x4000 + (address & 0x3FFF) as usize
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.registers.borrow().prg_bank & 0x10 == 0
+    }
+
+    fn load_register(&mut self, address: u16, data: u8) {
+        let mut registers = self.registers.borrow_mut();
+        match address {
+            0x0000..=0x1FFF => {
+                registers.control = data;
+                self.mirror_mode.replace(match data & 0x03 {
+                    0 => MirrorMode::SingleScreenLower,
+                    1 => MirrorMode::SingleScreenUpper,
+                    2 => MirrorMode::Vertical,
+                    _ => MirrorMode::Horizontal,
+                });
+            }
+            0x2000..=0x3FFF => registers.chr_bank0 = data,
+            0x4000..=0x5FFF => registers.chr_bank1 = data,
+            _ => registers.prg_bank = data,
+        }
+    }
+}
+
+// This interface is exposed for PRG-RAM ($6000-$7FFF) and PRG-ROM ($8000-$FFFF)
+impl Device for PrgMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF if self.prg_ram_enabled() => self.prg_ram.borrow()[address as usize],
+            0x0000..=0x1FFF => 0x00,
+            _ => self.prg_mem[self.map_address(address - 0x2000)],
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                if self.prg_ram_enabled() {
+                    self.prg_ram.borrow_mut()[address as usize] = data;
+                }
+            }
+            _ => {
+                let consecutive = {
+                    let mut registers = self.registers.borrow_mut();
+                    let consecutive = registers.cycles_since_write <= 1;
+                    registers.cycles_since_write = 0;
+                    consecutive
+                };
+                if consecutive {
+                    return;
+                }
+
+                // writing a value with bit 7 set resets the shift register
+                if data & 0x80 != 0 {
+                    self.shift_register = 0x00;
+                    self.shift_count = 0;
+                    self.registers.borrow_mut().control |= 0x0C;
+                    return;
+                }
+
+                self.shift_register |= (data & 0x01) << self.shift_count;
+                self.shift_count += 1;
+
+                // the fifth write copies the shift register into the register selected by address
+                if self.shift_count == 5 {
+                    let value = self.shift_register;
+                    self.load_register(address - 0x2000, value);
+                    self.shift_register = 0x00;
+                    self.shift_count = 0;
+                }
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        let registers = self.registers.borrow();
+        state.write_u8(registers.control);
+        state.write_u8(registers.chr_bank0);
+        state.write_u8(registers.chr_bank1);
+        state.write_u8(registers.prg_bank);
+        state.write_u8(registers.cycles_since_write);
+        state.write_u8(self.shift_register);
+        state.write_u8(self.shift_count);
+        state.write_bytes(&self.prg_ram.borrow());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut registers = self.registers.borrow_mut();
+        registers.control = state.read_u8()?;
+        registers.chr_bank0 = state.read_u8()?;
+        registers.chr_bank1 = state.read_u8()?;
+        registers.prg_bank = state.read_u8()?;
+        registers.cycles_since_write = state.read_u8()?;
+        self.shift_register = state.read_u8()?;
+        self.shift_count = state.read_u8()?;
+        if self.shift_count >= 5 {
+            return Err(StateError::Corrupted);
+        }
+        state.read_bytes_into(&mut self.prg_ram.borrow_mut())
+    }
+}
+
+// clocked every CPU cycle to tell consecutive writes apart
+impl Expansion for Registers {
+    fn clock(&mut self) {
+        self.cycles_since_write = self.cycles_since_write.saturating_add(1);
+    }
+}
+
+impl ChrMapper {
+    fn map_address(&self, address: u16) -> usize {
+        let registers = self.registers.borrow();
+        let address = if registers.control & 0x10 == 0 {
+            // switch 8 KiB at a time
+            (registers.chr_bank0 & 0x1E) as usize * 0x1000 + address as usize
+        } else if address < 0x1000 {
+            // switch two separate 4 KiB banks
+            registers.chr_bank0 as usize * 0x1000 + address as usize
+        } else {
+            registers.chr_bank1 as usize * 0x1000 + (address & 0x0FFF) as usize
+        };
+        address % self.chr_mem.len()
+    }
+}
+
+impl Device for ChrMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        self.chr_mem[self.map_address(address)]
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if self.chr_ram {
+            let address = self.map_address(address);
+            self.chr_mem[address] = data;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        if self.chr_ram {
+            state.write_bytes(&self.chr_mem);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if self.chr_ram {
+            state.read_bytes_into(&mut self.chr_mem)?;
+        }
+        Ok(())
+    }
+}
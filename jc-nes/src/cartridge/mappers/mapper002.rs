@@ -0,0 +1,149 @@
+use crate::bus::{Device, SharedMut};
+use crate::cartridge::{Cartridge, MirrorMode};
+use crate::state::{StateError, StateReader, StateWriter};
+
+// https://wiki.nesdev.com/w/index.php/UxROM
+// https://wiki.nesdev.com/w/index.php/AxROM (mapper 7)
+// Both boards switch PRG banks with any write to $8000-$FFFF and use 8 KiB of
+// unbanked CHR, AxROM switches 32 KiB at once and also selects the nametable
+#[derive(Clone, Copy)]
+pub enum Variant {
+    UxRom,
+    AxRom,
+}
+
+pub struct PrgMapper {
+    variant: Variant,
+    cur_bank: u8,
+    mirror_mode: SharedMut<MirrorMode>,
+    prg_mem: Vec<u8>,
+    prg_banks: usize,
+    prg_ram: SharedMut<Vec<u8>>,
+}
+
+pub struct ChrMapper {
+    chr_mem: Vec<u8>,
+    chr_ram: bool,
+}
+
+pub fn new_mapper(
+    cartridge: Cartridge,
+    mirror_mode: SharedMut<MirrorMode>,
+    variant: Variant,
+) -> (PrgMapper, ChrMapper) {
+    // AxROM ignores the header mirroring bit and starts on the lower nametable
+    if let Variant::AxRom = variant {
+        mirror_mode.replace(MirrorMode::SingleScreenLower);
+    }
+
+    let prg_mapper = PrgMapper {
+        variant,
+        cur_bank: 0,
+        mirror_mode,
+        prg_mem: cartridge.prg_rom,
+        prg_banks: cartridge.prg_banks,
+        prg_ram: cartridge.prg_ram,
+    };
+    let chr_mapper = ChrMapper {
+        chr_ram: cartridge.chr_banks == 0,
+        chr_mem: if cartridge.chr_banks == 0 {
+            vec![0u8; cartridge.chr_ram_size]
+        } else {
+            cartridge.chr_rom
+        },
+    };
+    (prg_mapper, chr_mapper)
+}
+
+impl PrgMapper {
+    fn map_address(&self, address: u16) -> usize {
+        match self.variant {
+            // switch 16 KiB at $8000, last bank fixed at $C000
+            Variant::UxRom => {
+                let bank = if address < 0x4000 {
+                    self.cur_bank as usize % self.prg_banks
+                } else {
+                    self.prg_banks - 1
+                };
+                bank * 0x4000 + (address & 0x3FFF) as usize
+            }
+            // switch 32 KiB at $8000
+            Variant::AxRom => {
+                (self.cur_bank as usize * 0x8000 + address as usize) % self.prg_mem.len()
+            }
+        }
+    }
+}
+
+// This interface is exposed for PRG-RAM ($6000-$7FFF) and PRG-ROM ($8000-$FFFF)
+impl Device for PrgMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.prg_ram.borrow()[address as usize],
+            _ => self.prg_mem[self.map_address(address - 0x2000)],
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x0000..=0x1FFF => self.prg_ram.borrow_mut()[address as usize] = data,
+            _ => match self.variant {
+                Variant::UxRom => self.cur_bank = data & 0x0F,
+                Variant::AxRom => {
+                    // bit 4 selects the nametable shown on all four screens
+                    self.cur_bank = data & 0x07;
+                    self.mirror_mode.replace(if data & 0x10 == 0 {
+                        MirrorMode::SingleScreenLower
+                    } else {
+                        MirrorMode::SingleScreenUpper
+                    });
+                }
+            },
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.cur_bank);
+        state.write_bytes(&self.prg_ram.borrow());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let bank = state.read_u8()?;
+        let bank_mask = match self.variant {
+            Variant::UxRom => 0x0F,
+            Variant::AxRom => 0x07,
+        };
+        if bank & !bank_mask != 0 {
+            return Err(StateError::Corrupted);
+        }
+        self.cur_bank = bank;
+        state.read_bytes_into(&mut self.prg_ram.borrow_mut())
+    }
+}
+
+impl Device for ChrMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        // less than 8 KiB of CHR-RAM is mirrored
+        self.chr_mem[address as usize % self.chr_mem.len()]
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if self.chr_ram {
+            let len = self.chr_mem.len();
+            self.chr_mem[address as usize % len] = data;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        if self.chr_ram {
+            state.write_bytes(&self.chr_mem);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if self.chr_ram {
+            state.read_bytes_into(&mut self.chr_mem)?;
+        }
+        Ok(())
+    }
+}
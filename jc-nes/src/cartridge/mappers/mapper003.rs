@@ -1,16 +1,19 @@
 use crate::bus::{Device, SharedMut};
 use crate::cartridge::Cartridge;
+use crate::state::{StateError, StateReader, StateWriter};
 use std::{cell::RefCell, rc::Rc};
 
 pub struct PrgMapper {
     cur_bank: SharedMut<usize>,
     prg_mem: Vec<u8>,
     prg_banks: usize,
+    prg_ram: SharedMut<Vec<u8>>,
 }
 
 pub struct ChrMapper {
     cur_bank: SharedMut<usize>,
     chr_mem: Vec<u8>,
+    chr_ram: bool,
 }
 
 pub fn new_mapper(cartridge: Cartridge) -> (PrgMapper, ChrMapper) {
@@ -20,11 +23,13 @@ pub fn new_mapper(cartridge: Cartridge) -> (PrgMapper, ChrMapper) {
         cur_bank: cur_bank.clone(),
         prg_mem: cartridge.prg_rom,
         prg_banks: cartridge.prg_banks,
+        prg_ram: cartridge.prg_ram,
     };
     let chr_mapper = ChrMapper {
         cur_bank,
+        chr_ram: cartridge.chr_banks == 0,
         chr_mem: if cartridge.chr_banks == 0 {
-            [0u8; 8 * 1024].to_vec()
+            vec![0u8; cartridge.chr_ram_size]
         } else {
             cartridge.chr_rom
         },
@@ -42,22 +47,67 @@ impl PrgMapper {
     }
 }
 
+// This interface is exposed for PRG-RAM ($6000-$7FFF) and PRG-ROM ($8000-$FFFF)
 impl Device for PrgMapper {
     fn read(&mut self, address: u16) -> u8 {
-        let address = self.map_address(address);
-        self.prg_mem[address as usize]
+        match address {
+            0x0000..=0x1FFF => self.prg_ram.borrow()[address as usize],
+            _ => self.prg_mem[self.map_address(address - 0x2000) as usize],
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x0000..=0x1FFF => self.prg_ram.borrow_mut()[address as usize] = data,
+            _ => {
+                self.cur_bank.replace((data & 0x03) as usize);
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(*self.cur_bank.borrow());
+        state.write_bytes(&self.prg_ram.borrow());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let bank = state.read_usize()?;
+        if bank > 0x03 {
+            return Err(StateError::Corrupted);
+        }
+        self.cur_bank.replace(bank);
+        state.read_bytes_into(&mut self.prg_ram.borrow_mut())
     }
+}
 
-    fn write(&mut self, _address: u16, data: u8) {
-        self.cur_bank.replace((data & 0x03) as usize);
+impl ChrMapper {
+    fn map_address(&self, address: u16) -> usize {
+        (*self.cur_bank.borrow() * 0x2000 + address as usize) % self.chr_mem.len()
     }
 }
 
 impl Device for ChrMapper {
     fn read(&mut self, address: u16) -> u8 {
-        let address = *self.cur_bank.borrow() * 0x2000 + address as usize;
-        self.chr_mem[address]
+        self.chr_mem[self.map_address(address)]
     }
 
-    fn write(&mut self, _address: u16, _data: u8) {}
+    fn write(&mut self, address: u16, data: u8) {
+        if self.chr_ram {
+            let address = self.map_address(address);
+            self.chr_mem[address] = data;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        if self.chr_ram {
+            state.write_bytes(&self.chr_mem);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if self.chr_ram {
+            state.read_bytes_into(&mut self.chr_mem)?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,222 @@
+use crate::bus::{Device, SharedMut};
+use crate::cartridge::{Cartridge, MirrorMode};
+use crate::state::{StateError, StateReader, StateWriter};
+use std::{cell::RefCell, rc::Rc};
+
+// https://wiki.nesdev.com/w/index.php/MMC3
+#[derive(Default)]
+pub struct Registers {
+    bank_select: u8,
+    banks: [u8; 8],
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+}
+
+pub struct PrgMapper {
+    registers: SharedMut<Registers>,
+    mirror_mode: SharedMut<MirrorMode>,
+    irq: SharedMut<bool>,
+    prg_mem: Vec<u8>,
+    prg_ram: SharedMut<Vec<u8>>,
+}
+
+pub struct ChrMapper {
+    registers: SharedMut<Registers>,
+    irq: SharedMut<bool>,
+    chr_mem: Vec<u8>,
+    chr_ram: bool,
+    last_a12: bool,
+}
+
+pub fn new_mapper(
+    cartridge: Cartridge,
+    mirror_mode: SharedMut<MirrorMode>,
+    irq: SharedMut<bool>,
+) -> (PrgMapper, ChrMapper) {
+    let registers = Rc::new(RefCell::new(Registers::default()));
+
+    let prg_mapper = PrgMapper {
+        registers: registers.clone(),
+        mirror_mode,
+        irq: irq.clone(),
+        prg_mem: cartridge.prg_rom,
+        prg_ram: cartridge.prg_ram,
+    };
+    let chr_mapper = ChrMapper {
+        registers,
+        irq,
+        chr_ram: cartridge.chr_banks == 0,
+        chr_mem: if cartridge.chr_banks == 0 {
+            vec![0u8; cartridge.chr_ram_size]
+        } else {
+            cartridge.chr_rom
+        },
+        last_a12: false,
+    };
+    (prg_mapper, chr_mapper)
+}
+
+impl PrgMapper {
+    fn map_address(&self, address: u16) -> usize {
+        let registers = self.registers.borrow();
+        let banks = self.prg_mem.len() / 0x2000;
+        let second_last = banks - 2;
+        let last = banks - 1;
+        let r6 = registers.banks[6] as usize;
+        let r7 = registers.banks[7] as usize;
+
+        // 8 KiB banks, bit 6 of bank select swaps $8000 and $C000
+        let bank = match (registers.bank_select & 0x40 != 0, address / 0x2000) {
+            (false, 0) => r6,
+            (true, 0) => second_last,
+            (_, 1) => r7,
+            (false, 2) => second_last,
+            (true, 2) => r6,
+            _ => last,
+        };
+
+        (bank % banks) * 0x2000 + (address & 0x1FFF) as usize
+    }
+}
+
+// This interface is exposed for PRG-RAM ($6000-$7FFF) and PRG-ROM ($8000-$FFFF)
+impl Device for PrgMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.prg_ram.borrow()[address as usize],
+            _ => self.prg_mem[self.map_address(address - 0x2000)],
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        let mut registers = self.registers.borrow_mut();
+        match (address, address & 0x01) {
+            (0x0000..=0x1FFF, _) => self.prg_ram.borrow_mut()[address as usize] = data,
+            (0x2000..=0x3FFF, 0) => registers.bank_select = data,
+            (0x2000..=0x3FFF, _) => {
+                let bank = (registers.bank_select & 0x07) as usize;
+                registers.banks[bank] = data;
+            }
+            // boards wired for four-screen VRAM ignore the mirroring register
+            (0x4000..=0x5FFF, 0) if *self.mirror_mode.borrow() == MirrorMode::FourScreen => (),
+            (0x4000..=0x5FFF, 0) => {
+                self.mirror_mode.replace(if data & 0x01 == 0 {
+                    MirrorMode::Vertical
+                } else {
+                    MirrorMode::Horizontal
+                });
+            }
+            (0x4000..=0x5FFF, _) => (),
+            (0x6000..=0x7FFF, 0) => registers.irq_latch = data,
+            (0x6000..=0x7FFF, _) => {
+                registers.irq_counter = 0;
+                registers.irq_reload = true;
+            }
+            (_, 0) => {
+                registers.irq_enabled = false;
+                self.irq.replace(false);
+            }
+            (_, _) => registers.irq_enabled = true,
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        let registers = self.registers.borrow();
+        state.write_u8(registers.bank_select);
+        for bank in registers.banks {
+            state.write_u8(bank);
+        }
+        state.write_u8(registers.irq_latch);
+        state.write_u8(registers.irq_counter);
+        state.write_bool(registers.irq_reload);
+        state.write_bool(registers.irq_enabled);
+        state.write_bytes(&self.prg_ram.borrow());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut registers = self.registers.borrow_mut();
+        registers.bank_select = state.read_u8()?;
+        for bank in registers.banks.iter_mut() {
+            *bank = state.read_u8()?;
+        }
+        registers.irq_latch = state.read_u8()?;
+        registers.irq_counter = state.read_u8()?;
+        registers.irq_reload = state.read_bool()?;
+        registers.irq_enabled = state.read_bool()?;
+        state.read_bytes_into(&mut self.prg_ram.borrow_mut())
+    }
+}
+
+impl ChrMapper {
+    fn map_address(&self, address: u16) -> usize {
+        let registers = self.registers.borrow();
+
+        // bit 7 of bank select swaps the 2 KiB and 1 KiB bank halves
+        let address = if registers.bank_select & 0x80 != 0 {
+            address ^ 0x1000
+        } else {
+            address
+        };
+
+        let bank = match address / 0x0400 {
+            0 => registers.banks[0] & 0xFE,
+            1 => registers.banks[0] | 0x01,
+            2 => registers.banks[1] & 0xFE,
+            3 => registers.banks[1] | 0x01,
+            slot => registers.banks[slot as usize - 2],
+        } as usize;
+
+        (bank * 0x0400 + (address & 0x03FF) as usize) % self.chr_mem.len()
+    }
+
+    // the scanline counter is clocked by rising edges of PPU address line A12
+    fn watch_a12(&mut self, address: u16) {
+        let a12 = address & 0x1000 != 0;
+        if a12 && !self.last_a12 {
+            let mut registers = self.registers.borrow_mut();
+            if registers.irq_counter == 0 || registers.irq_reload {
+                registers.irq_counter = registers.irq_latch;
+                registers.irq_reload = false;
+            } else {
+                registers.irq_counter -= 1;
+            }
+
+            if registers.irq_counter == 0 && registers.irq_enabled {
+                self.irq.replace(true);
+            }
+        }
+        self.last_a12 = a12;
+    }
+}
+
+impl Device for ChrMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        self.watch_a12(address);
+        self.chr_mem[self.map_address(address)]
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self.watch_a12(address);
+        if self.chr_ram {
+            let address = self.map_address(address);
+            self.chr_mem[address] = data;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.last_a12);
+        if self.chr_ram {
+            state.write_bytes(&self.chr_mem);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.last_a12 = state.read_bool()?;
+        if self.chr_ram {
+            state.read_bytes_into(&mut self.chr_mem)?;
+        }
+        Ok(())
+    }
+}
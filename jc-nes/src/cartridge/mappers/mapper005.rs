@@ -0,0 +1,649 @@
+use crate::apu::pulse::Pulse;
+use crate::bus::{Device, Fetch, SharedMut};
+use crate::cartridge::mappers::Expansion;
+use crate::cartridge::Cartridge;
+use crate::state::{StateError, StateReader, StateWriter};
+use std::{cell::RefCell, rc::Rc};
+
+// envelopes and length counters are clocked at a fixed 240 Hz
+const FRAME_PERIOD: u16 = 7457;
+
+// a full scale PCM sample is about as loud as both pulses at full volume
+const PCM_LEVEL: f32 = 0.2585;
+
+// a scanline is about 114 CPU cycles, if one goes by without the PPU starting
+// another it stopped rendering
+const IDLE_CYCLES: u8 = 120;
+
+// https://wiki.nesdev.com/w/index.php/MMC5
+pub struct Mmc5 {
+    irq: SharedMut<bool>,
+    prg_mode: u8,
+    chr_mode: u8,
+    ram_protect: [u8; 2],
+    exram_mode: u8,
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    // $5113-$5117
+    prg_banks: [u8; 5],
+    // set A ($5120-$5127) and set B ($5128-$512B) with the upper bits from $5130
+    chr_banks_a: [u16; 8],
+    chr_banks_b: [u16; 4],
+    chr_upper: u8,
+    last_set_b: bool,
+    split_control: u8,
+    split_scroll: u8,
+    split_page: u8,
+    irq_target: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    multiplicand: u8,
+    multiplier: u8,
+    exram: [u8; 1024],
+
+    // scanline detection and per tile state, tracked from the PPU fetches
+    in_frame: bool,
+    scanline: u16,
+    idle_cycles: u8,
+    fetching_sprites: bool,
+    tile_fetches: u8,
+    split_tile: bool,
+    split_column: u8,
+    split_y: u8,
+    ex_attribute: u8,
+
+    // expansion audio
+    pulse1: Pulse,
+    pulse2: Pulse,
+    pcm: u8,
+    frame_timer: u16,
+    odd_cycle: bool,
+}
+
+// 8 KiB bank of PRG-ROM or PRG-RAM
+enum PrgBank {
+    Rom(usize),
+    Ram(usize),
+}
+
+// what one of the four nametables is mapped to by $5105
+enum NametableSource {
+    Ciram(usize),
+    ExRam,
+    Fill,
+}
+
+pub struct PrgMapper {
+    mmc5: SharedMut<Mmc5>,
+    prg_mem: Vec<u8>,
+    prg_ram: SharedMut<Vec<u8>>,
+}
+
+pub struct ChrMapper {
+    mmc5: SharedMut<Mmc5>,
+    chr_mem: Vec<u8>,
+    chr_ram: bool,
+}
+
+pub struct Nametables {
+    mmc5: SharedMut<Mmc5>,
+    ciram: [u8; 2 * 1024],
+}
+
+pub fn new_mapper(
+    cartridge: Cartridge,
+    irq: SharedMut<bool>,
+) -> (PrgMapper, ChrMapper, Nametables, SharedMut<Mmc5>) {
+    let mmc5 = Rc::new(RefCell::new(Mmc5 {
+        irq,
+        prg_mode: 3,
+        chr_mode: 0,
+        ram_protect: [0; 2],
+        exram_mode: 0,
+        nametable_mapping: 0,
+        fill_tile: 0,
+        fill_attribute: 0,
+        prg_banks: [0, 0, 0, 0, 0xFF],
+        chr_banks_a: [0; 8],
+        chr_banks_b: [0; 4],
+        chr_upper: 0,
+        last_set_b: false,
+        split_control: 0,
+        split_scroll: 0,
+        split_page: 0,
+        irq_target: 0,
+        irq_enabled: false,
+        irq_pending: false,
+        multiplicand: 0xFF,
+        multiplier: 0xFF,
+        exram: [0u8; 1024],
+        in_frame: false,
+        scanline: 0,
+        idle_cycles: 0,
+        fetching_sprites: false,
+        tile_fetches: 0,
+        split_tile: false,
+        split_column: 0,
+        split_y: 0,
+        ex_attribute: 0,
+        pulse1: Pulse::without_sweep(),
+        pulse2: Pulse::without_sweep(),
+        pcm: 0,
+        frame_timer: 0,
+        odd_cycle: false,
+    }));
+
+    let prg_mapper = PrgMapper {
+        mmc5: mmc5.clone(),
+        prg_mem: cartridge.prg_rom,
+        prg_ram: cartridge.prg_ram,
+    };
+    let chr_mapper = ChrMapper {
+        mmc5: mmc5.clone(),
+        chr_ram: cartridge.chr_banks == 0,
+        chr_mem: if cartridge.chr_banks == 0 {
+            vec![0u8; cartridge.chr_ram_size]
+        } else {
+            cartridge.chr_rom
+        },
+    };
+    let nametables = Nametables {
+        mmc5: mmc5.clone(),
+        ciram: [0u8; 2 * 1024],
+    };
+    (prg_mapper, chr_mapper, nametables, mmc5)
+}
+
+impl Mmc5 {
+    // bank mapped at a CPU address in $6000-$FFFF
+    fn prg_bank(&self, address: u16) -> PrgBank {
+        if address < 0x8000 {
+            return PrgBank::Ram((self.prg_banks[0] & 0x07) as usize);
+        }
+
+        // register ($5114-$5117) and 8 KiB bank within it for each banking mode
+        let slot = ((address - 0x8000) / 0x2000) as usize;
+        let (register, bank) = match self.prg_mode {
+            0 => (4, (self.prg_banks[4] & 0x7C) + slot as u8),
+            1 => {
+                let register = if slot < 2 { 2 } else { 4 };
+                (
+                    register,
+                    (self.prg_banks[register] & 0x7E) + (slot & 0x01) as u8,
+                )
+            }
+            2 => match slot {
+                0 | 1 => (2, (self.prg_banks[2] & 0x7E) + slot as u8),
+                2 => (3, self.prg_banks[3]),
+                _ => (4, self.prg_banks[4]),
+            },
+            _ => (slot + 1, self.prg_banks[slot + 1]),
+        };
+
+        // $5117 always maps ROM, the others only with bit 7 set
+        if register == 4 || self.prg_banks[register] & 0x80 != 0 {
+            PrgBank::Rom((bank & 0x7F) as usize)
+        } else {
+            PrgBank::Ram((bank & 0x07) as usize)
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.ram_protect == [0x02, 0x01]
+    }
+
+    // offset into CHR memory through the A or B bank set, set B repeats every 4 KiB
+    fn chr_address(&self, address: u16, set_b: bool) -> usize {
+        let (bank, size) = match (self.chr_mode, set_b) {
+            (0, false) => (self.chr_banks_a[7], 0x2000),
+            (1, false) => (
+                self.chr_banks_a[3 + 4 * (address / 0x1000) as usize],
+                0x1000,
+            ),
+            (2, false) => (
+                self.chr_banks_a[1 + 2 * (address / 0x0800) as usize],
+                0x0800,
+            ),
+            (_, false) => (self.chr_banks_a[(address / 0x0400) as usize], 0x0400),
+            (0, true) => (self.chr_banks_b[3], 0x2000),
+            (1, true) => (self.chr_banks_b[3], 0x1000),
+            (2, true) => (
+                self.chr_banks_b[1 + 2 * ((address / 0x0800) & 0x01) as usize],
+                0x0800,
+            ),
+            (_, true) => (
+                self.chr_banks_b[((address / 0x0400) & 0x03) as usize],
+                0x0400,
+            ),
+        };
+        bank as usize * size + (address as usize & (size - 1))
+    }
+
+    fn nametable_source(&self, address: u16) -> NametableSource {
+        let nametable = (address / 0x0400) % 4;
+        match (self.nametable_mapping >> (nametable * 2)) & 0x03 {
+            page @ 0..=1 => NametableSource::Ciram(page as usize),
+            2 => NametableSource::ExRam,
+            _ => NametableSource::Fill,
+        }
+    }
+
+    // this PPU fetches sprites for the next line at the end of the current one, so
+    // the first background fetch after them marks a new scanline
+    fn clock_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline += 1;
+            if self.scanline == 240 {
+                self.in_frame = false;
+            } else if self.scanline == self.irq_target as u16 {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+            self.irq_pending = false;
+        }
+        self.tile_fetches = 0;
+        self.idle_cycles = 0;
+        self.update_irq();
+    }
+
+    // background tile fetch, replaced by the split region tile if inside it
+    fn nametable_fetch(&mut self, address: u16) -> Option<u8> {
+        if self.fetching_sprites {
+            self.fetching_sprites = false;
+            self.clock_scanline();
+        }
+
+        // after the dummy fetch the PPU fetches tiles 2-33 of the current line,
+        // then tiles 0-1 of the next one
+        let fetch = self.tile_fetches;
+        self.tile_fetches = fetch.saturating_add(1);
+        let (column, line) = match fetch {
+            1..=32 => (fetch + 1, self.scanline),
+            33 | 34 => (fetch - 33, self.scanline + 1),
+            _ => {
+                self.split_tile = false;
+                return None;
+            }
+        };
+
+        let tiles = self.split_control & 0x1F;
+        self.split_tile = self.in_frame
+            && self.split_control & 0x80 != 0
+            && self.exram_mode <= 1
+            && if self.split_control & 0x40 != 0 {
+                column >= tiles
+            } else {
+                column < tiles
+            };
+
+        if self.split_tile {
+            let y = (self.split_scroll as u16 + line) % 240;
+            self.split_y = y as u8;
+            self.split_column = column % 32;
+            return Some(self.exram[(y / 8 * 32) as usize + self.split_column as usize]);
+        }
+
+        if self.exram_mode == 1 {
+            self.ex_attribute = self.exram[(address & 0x03FF) as usize];
+        }
+        None
+    }
+
+    // attributes come from the split region or from ExRAM in extended attribute mode
+    fn attribute_fetch(&self) -> Option<u8> {
+        let palette = if self.split_tile {
+            let (y, column) = (self.split_y as usize, self.split_column as usize);
+            let attribute = self.exram[0x03C0 + y / 32 * 8 + column / 4];
+            let shift = ((y / 16) & 0x01) * 4 + ((column / 2) & 0x01) * 2;
+            (attribute >> shift) & 0x03
+        } else if self.exram_mode == 1 {
+            self.ex_attribute >> 6
+        } else {
+            return None;
+        };
+        // the same palette for every quadrant, whichever one the PPU picks
+        Some(palette * 0x55)
+    }
+
+    fn update_irq(&mut self) {
+        self.irq.replace(self.irq_pending && self.irq_enabled);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.prg_mode);
+        state.write_u8(self.chr_mode);
+        state.write_bytes(&self.ram_protect);
+        state.write_u8(self.exram_mode);
+        state.write_u8(self.nametable_mapping);
+        state.write_u8(self.fill_tile);
+        state.write_u8(self.fill_attribute);
+        state.write_bytes(&self.prg_banks);
+        for bank in self.chr_banks_a.iter().chain(self.chr_banks_b.iter()) {
+            state.write_u16(*bank);
+        }
+        state.write_u8(self.chr_upper);
+        state.write_bool(self.last_set_b);
+        state.write_u8(self.split_control);
+        state.write_u8(self.split_scroll);
+        state.write_u8(self.split_page);
+        state.write_u8(self.irq_target);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_pending);
+        state.write_u8(self.multiplicand);
+        state.write_u8(self.multiplier);
+        state.write_bytes(&self.exram);
+        state.write_bool(self.in_frame);
+        state.write_u16(self.scanline);
+        state.write_u8(self.idle_cycles);
+        state.write_bool(self.fetching_sprites);
+        state.write_u8(self.tile_fetches);
+        state.write_bool(self.split_tile);
+        state.write_u8(self.split_column);
+        state.write_u8(self.split_y);
+        state.write_u8(self.ex_attribute);
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        state.write_u8(self.pcm);
+        state.write_u16(self.frame_timer);
+        state.write_bool(self.odd_cycle);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_mode = state.read_u8()? & 0x03;
+        self.chr_mode = state.read_u8()? & 0x03;
+        state.read_bytes_into(&mut self.ram_protect)?;
+        self.exram_mode = state.read_u8()? & 0x03;
+        self.nametable_mapping = state.read_u8()?;
+        self.fill_tile = state.read_u8()?;
+        self.fill_attribute = state.read_u8()? & 0x03;
+        state.read_bytes_into(&mut self.prg_banks)?;
+        for bank in self
+            .chr_banks_a
+            .iter_mut()
+            .chain(self.chr_banks_b.iter_mut())
+        {
+            *bank = state.read_u16()? & 0x03FF;
+        }
+        self.chr_upper = state.read_u8()? & 0x03;
+        self.last_set_b = state.read_bool()?;
+        self.split_control = state.read_u8()?;
+        self.split_scroll = state.read_u8()?;
+        self.split_page = state.read_u8()?;
+        self.irq_target = state.read_u8()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        self.multiplicand = state.read_u8()?;
+        self.multiplier = state.read_u8()?;
+        state.read_bytes_into(&mut self.exram)?;
+        self.in_frame = state.read_bool()?;
+        self.scanline = state.read_u16()?;
+        self.idle_cycles = state.read_u8()?;
+        self.fetching_sprites = state.read_bool()?;
+        self.tile_fetches = state.read_u8()?;
+        self.split_tile = state.read_bool()?;
+        self.split_column = state.read_u8()? % 32;
+        self.split_y = state.read_u8()?;
+        if self.split_y >= 240 {
+            return Err(StateError::Corrupted);
+        }
+        self.ex_attribute = state.read_u8()?;
+        self.pulse1.load_state(state)?;
+        self.pulse2.load_state(state)?;
+        self.pcm = state.read_u8()?;
+        self.frame_timer = state.read_u16()?;
+        self.odd_cycle = state.read_bool()?;
+        Ok(())
+    }
+}
+
+// This interface is exposed for the registers, audio and ExRAM ($4020-$5FFF)
+impl Device for Mmc5 {
+    fn read(&mut self, address: u16) -> u8 {
+        match address + 0x4020 {
+            0x5015 => {
+                self.pulse1.length_counter.active() as u8
+                    | (self.pulse2.length_counter.active() as u8) << 1
+            }
+            0x5204 => {
+                let data = (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6;
+                self.irq_pending = false;
+                self.update_irq();
+                data
+            }
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            register @ 0x5C00..=0x5FFF if self.exram_mode >= 2 => {
+                self.exram[(register - 0x5C00) as usize]
+            }
+            _ => 0x00,
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address + 0x4020 {
+            register @ 0x5000..=0x5003 => self.pulse1.write(register & 0x03, data),
+            register @ 0x5004..=0x5007 => self.pulse2.write(register & 0x03, data),
+            // zero writes are ignored in PCM write mode
+            0x5011 if data != 0 => self.pcm = data,
+            0x5015 => {
+                self.pulse1.length_counter.set_enabled(data & 0x01 != 0);
+                self.pulse2.length_counter.set_enabled(data & 0x02 != 0);
+            }
+            0x5100 => self.prg_mode = data & 0x03,
+            0x5101 => self.chr_mode = data & 0x03,
+            0x5102 => self.ram_protect[0] = data & 0x03,
+            0x5103 => self.ram_protect[1] = data & 0x03,
+            0x5104 => self.exram_mode = data & 0x03,
+            0x5105 => self.nametable_mapping = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attribute = data & 0x03,
+            register @ 0x5113..=0x5117 => self.prg_banks[(register - 0x5113) as usize] = data,
+            register @ 0x5120..=0x5127 => {
+                self.chr_banks_a[(register - 0x5120) as usize] =
+                    data as u16 | (self.chr_upper as u16) << 8;
+                self.last_set_b = false;
+            }
+            register @ 0x5128..=0x512B => {
+                self.chr_banks_b[(register - 0x5128) as usize] =
+                    data as u16 | (self.chr_upper as u16) << 8;
+                self.last_set_b = true;
+            }
+            0x5130 => self.chr_upper = data & 0x03,
+            0x5200 => self.split_control = data,
+            0x5201 => self.split_scroll = data,
+            0x5202 => self.split_page = data,
+            0x5203 => self.irq_target = data,
+            0x5204 => {
+                self.irq_enabled = data & 0x80 != 0;
+                self.update_irq();
+            }
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            register @ 0x5C00..=0x5FFF => {
+                let address = (register - 0x5C00) as usize;
+                match self.exram_mode {
+                    // as nametable or attribute memory it only takes writes while rendering
+                    0 | 1 => self.exram[address] = if self.in_frame { data } else { 0x00 },
+                    2 => self.exram[address] = data,
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Expansion for Mmc5 {
+    fn clock(&mut self) {
+        if self.in_frame {
+            self.idle_cycles = self.idle_cycles.saturating_add(1);
+            if self.idle_cycles >= IDLE_CYCLES {
+                self.in_frame = false;
+            }
+        }
+
+        self.odd_cycle = !self.odd_cycle;
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+
+        self.frame_timer += 1;
+        if self.frame_timer >= FRAME_PERIOD {
+            self.frame_timer = 0;
+            for pulse in [&mut self.pulse1, &mut self.pulse2] {
+                pulse.envelope.clock();
+                pulse.length_counter.clock();
+            }
+        }
+    }
+
+    // pulses are mixed like the APU ones
+    fn output(&self) -> f32 {
+        let pulse = (self.pulse1.output() + self.pulse2.output()) as f32;
+        let pulse = if pulse == 0.0 {
+            0.0
+        } else {
+            95.52 / (8128.0 / pulse + 100.0)
+        };
+        pulse + self.pcm as f32 / 255.0 * PCM_LEVEL
+    }
+}
+
+// This interface is exposed for PRG-RAM ($6000-$7FFF) and PRG-ROM ($8000-$FFFF)
+impl Device for PrgMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        let address = address + 0x6000;
+
+        // the NMI vector fetch marks the end of the frame
+        if address == 0xFFFA || address == 0xFFFB {
+            self.mmc5.borrow_mut().in_frame = false;
+        }
+
+        match self.mmc5.borrow().prg_bank(address) {
+            PrgBank::Rom(bank) => {
+                self.prg_mem[(bank * 0x2000 + (address & 0x1FFF) as usize) % self.prg_mem.len()]
+            }
+            PrgBank::Ram(bank) => {
+                let prg_ram = self.prg_ram.borrow();
+                prg_ram[(bank * 0x2000 + (address & 0x1FFF) as usize) % prg_ram.len()]
+            }
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        let address = address + 0x6000;
+        let mmc5 = self.mmc5.borrow();
+        if let PrgBank::Ram(bank) = mmc5.prg_bank(address) {
+            if mmc5.prg_ram_writable() {
+                let mut prg_ram = self.prg_ram.borrow_mut();
+                let address = (bank * 0x2000 + (address & 0x1FFF) as usize) % prg_ram.len();
+                prg_ram[address] = data;
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.mmc5.borrow().save_state(state);
+        state.write_bytes(&self.prg_ram.borrow());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.mmc5.borrow_mut().load_state(state)?;
+        state.read_bytes_into(&mut self.prg_ram.borrow_mut())
+    }
+}
+
+impl Device for ChrMapper {
+    // accesses through $2007 use the last written bank set
+    fn read(&mut self, address: u16) -> u8 {
+        let mmc5 = self.mmc5.borrow();
+        let address = mmc5.chr_address(address, mmc5.last_set_b);
+        self.chr_mem[address % self.chr_mem.len()]
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if self.chr_ram {
+            let mmc5 = self.mmc5.borrow();
+            let address = mmc5.chr_address(address, mmc5.last_set_b) % self.chr_mem.len();
+            self.chr_mem[address] = data;
+        }
+    }
+
+    // with 8x16 sprites the background uses set B, anything else set A
+    fn fetch(&mut self, address: u16, fetch: Fetch) -> u8 {
+        let mut mmc5 = self.mmc5.borrow_mut();
+        let address = if fetch.sprite {
+            mmc5.fetching_sprites = true;
+            mmc5.chr_address(address, false)
+        } else if mmc5.split_tile {
+            // split tiles use their own fine Y in the 4 KiB page from $5202
+            let fine_y = (mmc5.split_y & 0x07) as usize;
+            mmc5.split_page as usize * 0x1000 + (address & 0x0FF8) as usize + fine_y
+        } else if mmc5.exram_mode == 1 {
+            let bank = (mmc5.ex_attribute & 0x3F) as usize | (mmc5.chr_upper as usize) << 6;
+            bank * 0x1000 + (address & 0x0FFF) as usize
+        } else {
+            mmc5.chr_address(address, fetch.tall_sprites)
+        };
+        self.chr_mem[address % self.chr_mem.len()]
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        if self.chr_ram {
+            state.write_bytes(&self.chr_mem);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if self.chr_ram {
+            state.read_bytes_into(&mut self.chr_mem)?;
+        }
+        Ok(())
+    }
+}
+
+// This interface is exposed for the nametables ($2000-$2FFF on PPU Bus)
+impl Device for Nametables {
+    fn read(&mut self, address: u16) -> u8 {
+        let mmc5 = self.mmc5.borrow();
+        let offset = (address & 0x03FF) as usize;
+        match mmc5.nametable_source(address) {
+            NametableSource::Ciram(page) => self.ciram[page * 0x0400 + offset],
+            NametableSource::ExRam if mmc5.exram_mode <= 1 => mmc5.exram[offset],
+            NametableSource::ExRam => 0x00,
+            NametableSource::Fill if offset < 0x03C0 => mmc5.fill_tile,
+            NametableSource::Fill => mmc5.fill_attribute * 0x55,
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        let mut mmc5 = self.mmc5.borrow_mut();
+        let offset = (address & 0x03FF) as usize;
+        match mmc5.nametable_source(address) {
+            NametableSource::Ciram(page) => self.ciram[page * 0x0400 + offset] = data,
+            NametableSource::ExRam if mmc5.exram_mode <= 1 => mmc5.exram[offset] = data,
+            _ => (),
+        }
+    }
+
+    fn fetch(&mut self, address: u16, _fetch: Fetch) -> u8 {
+        let replaced = if address & 0x03FF < 0x03C0 {
+            self.mmc5.borrow_mut().nametable_fetch(address)
+        } else {
+            self.mmc5.borrow().attribute_fetch()
+        };
+        replaced.unwrap_or_else(|| self.read(address))
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ciram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ciram)
+    }
+}
@@ -0,0 +1,13 @@
+use crate::bus::SharedMut;
+use crate::cartridge::mappers::mapper002::{self, ChrMapper, PrgMapper, Variant};
+use crate::cartridge::{Cartridge, MirrorMode};
+
+// https://wiki.nesdev.com/w/index.php/AxROM
+// UxROM with 32 KiB PRG banks and a register bit that picks the nametable, the
+// banking lives in mapper002
+pub fn new_mapper(
+    cartridge: Cartridge,
+    mirror_mode: SharedMut<MirrorMode>,
+) -> (PrgMapper, ChrMapper) {
+    mapper002::new_mapper(cartridge, mirror_mode, Variant::AxRom)
+}
@@ -0,0 +1,194 @@
+use crate::bus::{Device, SharedMut};
+use crate::cartridge::{Cartridge, MirrorMode};
+use crate::state::{StateError, StateReader, StateWriter};
+use std::{cell::RefCell, rc::Rc};
+
+// https://wiki.nesdev.com/w/index.php/MMC2
+// https://wiki.nesdev.com/w/index.php/MMC4 (mapper 10)
+// MMC4 has the same CHR latches and registers, with 16 KiB PRG banks
+#[derive(Clone, Copy)]
+pub enum Chip {
+    Mmc2,
+    Mmc4,
+}
+
+#[derive(Default)]
+pub struct Registers {
+    prg_bank: u8,
+    // 4 KiB CHR banks for $0000 ($FD, $FE) and $1000 ($FD, $FE)
+    chr_banks: [u8; 4],
+}
+
+pub struct PrgMapper {
+    chip: Chip,
+    registers: SharedMut<Registers>,
+    mirror_mode: SharedMut<MirrorMode>,
+    prg_mem: Vec<u8>,
+    prg_ram: SharedMut<Vec<u8>>,
+}
+
+pub struct ChrMapper {
+    chip: Chip,
+    registers: SharedMut<Registers>,
+    latches: [u8; 2],
+    chr_mem: Vec<u8>,
+    chr_ram: bool,
+}
+
+pub fn new_mapper(
+    cartridge: Cartridge,
+    mirror_mode: SharedMut<MirrorMode>,
+    chip: Chip,
+) -> (PrgMapper, ChrMapper) {
+    let registers = Rc::new(RefCell::new(Registers::default()));
+
+    let prg_mapper = PrgMapper {
+        chip,
+        registers: registers.clone(),
+        mirror_mode,
+        prg_mem: cartridge.prg_rom,
+        prg_ram: cartridge.prg_ram,
+    };
+    let chr_mapper = ChrMapper {
+        chip,
+        registers,
+        latches: [0xFE, 0xFE],
+        chr_ram: cartridge.chr_banks == 0,
+        chr_mem: if cartridge.chr_banks == 0 {
+            vec![0u8; cartridge.chr_ram_size]
+        } else {
+            cartridge.chr_rom
+        },
+    };
+    (prg_mapper, chr_mapper)
+}
+
+impl PrgMapper {
+    fn map_address(&self, address: u16) -> usize {
+        let prg_bank = self.registers.borrow().prg_bank as usize;
+        match self.chip {
+            // switch 8 KiB at $8000, last three 8 KiB banks fixed at $A000
+            Chip::Mmc2 => {
+                let banks = self.prg_mem.len() / 0x2000;
+                let bank = match address / 0x2000 {
+                    0 => prg_bank % banks,
+                    slot => banks.saturating_sub(4 - slot as usize),
+                };
+                bank * 0x2000 + (address & 0x1FFF) as usize
+            }
+            // switch 16 KiB at $8000, last bank fixed at $C000
+            Chip::Mmc4 => {
+                let banks = self.prg_mem.len() / 0x4000;
+                let bank = if address < 0x4000 {
+                    prg_bank % banks
+                } else {
+                    banks - 1
+                };
+                bank * 0x4000 + (address & 0x3FFF) as usize
+            }
+        }
+    }
+}
+
+// This interface is exposed for PRG-RAM ($6000-$7FFF) and PRG-ROM ($8000-$FFFF)
+impl Device for PrgMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.prg_ram.borrow()[address as usize],
+            _ => self.prg_mem[self.map_address(address - 0x2000)],
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        let mut registers = self.registers.borrow_mut();
+        match address {
+            0x0000..=0x1FFF => self.prg_ram.borrow_mut()[address as usize] = data,
+            0x4000..=0x4FFF => registers.prg_bank = data & 0x0F,
+            0x5000..=0x5FFF => registers.chr_banks[0] = data & 0x1F,
+            0x6000..=0x6FFF => registers.chr_banks[1] = data & 0x1F,
+            0x7000..=0x7FFF => registers.chr_banks[2] = data & 0x1F,
+            0x8000..=0x8FFF => registers.chr_banks[3] = data & 0x1F,
+            0x9000..=0x9FFF => {
+                self.mirror_mode.replace(if data & 0x01 == 0 {
+                    MirrorMode::Vertical
+                } else {
+                    MirrorMode::Horizontal
+                });
+            }
+            _ => (),
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        let registers = self.registers.borrow();
+        state.write_u8(registers.prg_bank);
+        state.write_bytes(&registers.chr_banks);
+        state.write_bytes(&self.prg_ram.borrow());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut registers = self.registers.borrow_mut();
+        registers.prg_bank = state.read_u8()?;
+        state.read_bytes_into(&mut registers.chr_banks)?;
+        state.read_bytes_into(&mut self.prg_ram.borrow_mut())
+    }
+}
+
+impl ChrMapper {
+    fn map_address(&self, address: u16) -> usize {
+        let table = (address / 0x1000) as usize;
+        let latch = (self.latches[table] == 0xFE) as usize;
+        let bank = self.registers.borrow().chr_banks[table * 2 + latch] as usize;
+        (bank * 0x1000 + (address & 0x0FFF) as usize) % self.chr_mem.len()
+    }
+
+    // latch 1 flips on fetches of $1FD8-$1FDF and $1FE8-$1FEF, latch 0 on
+    // $0FD8 and $0FE8 only for MMC2 and on the same ranges for MMC4
+    fn watch_latches(&mut self, address: u16) {
+        match (self.chip, address) {
+            (Chip::Mmc2, 0x0FD8) | (Chip::Mmc4, 0x0FD8..=0x0FDF) => self.latches[0] = 0xFD,
+            (Chip::Mmc2, 0x0FE8) | (Chip::Mmc4, 0x0FE8..=0x0FEF) => self.latches[0] = 0xFE,
+            (_, 0x1FD8..=0x1FDF) => self.latches[1] = 0xFD,
+            (_, 0x1FE8..=0x1FEF) => self.latches[1] = 0xFE,
+            _ => (),
+        }
+    }
+}
+
+impl Device for ChrMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        // the fetch that hits a latch address still uses the old bank
+        let data = self.chr_mem[self.map_address(address)];
+        self.watch_latches(address);
+        data
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if self.chr_ram {
+            let address = self.map_address(address);
+            self.chr_mem[address] = data;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.latches);
+        if self.chr_ram {
+            state.write_bytes(&self.chr_mem);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.latches)?;
+        if self
+            .latches
+            .iter()
+            .any(|&latch| latch != 0xFD && latch != 0xFE)
+        {
+            return Err(StateError::Corrupted);
+        }
+        if self.chr_ram {
+            state.read_bytes_into(&mut self.chr_mem)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,13 @@
+use crate::bus::SharedMut;
+use crate::cartridge::mappers::mapper009::{self, Chip, ChrMapper, PrgMapper};
+use crate::cartridge::{Cartridge, MirrorMode};
+
+// https://wiki.nesdev.com/w/index.php/MMC4
+// MMC2 with 16 KiB PRG banks and wider latch triggers, the latches live in
+// mapper009
+pub fn new_mapper(
+    cartridge: Cartridge,
+    mirror_mode: SharedMut<MirrorMode>,
+) -> (PrgMapper, ChrMapper) {
+    mapper009::new_mapper(cartridge, mirror_mode, Chip::Mmc4)
+}
@@ -0,0 +1,9 @@
+use crate::cartridge::mappers::mapper066::{self, ChrMapper, PrgMapper};
+use crate::cartridge::Cartridge;
+
+// https://wiki.nesdev.com/w/index.php/Color_Dreams
+// GxROM with the PRG and CHR bank bits swapped around and a wider CHR bank,
+// the banking lives in mapper066
+pub fn new_mapper(cartridge: Cartridge) -> (PrgMapper, ChrMapper) {
+    mapper066::new_mapper(cartridge, mapper066::color_dreams)
+}
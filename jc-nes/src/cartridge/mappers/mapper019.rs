@@ -0,0 +1,408 @@
+use crate::bus::{Device, SharedMut};
+use crate::cartridge::mappers::Expansion;
+use crate::cartridge::Cartridge;
+use crate::state::{StateError, StateReader, StateWriter};
+use std::{cell::RefCell, rc::Rc};
+
+// one N163 channel at full volume is about as loud as an APU pulse at full volume
+const OUTPUT_LEVEL: f32 = 0.00125;
+
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_019
+#[derive(Default)]
+pub struct Registers {
+    chr_banks: [u8; 8],
+    nametable_banks: [u8; 4],
+    prg_banks: [u8; 3],
+    ram_protect: u8,
+}
+
+// 1 KiB page of PPU memory, taken either from CHR memory or from the console CIRAM
+#[derive(Clone, Copy)]
+enum Page {
+    Chr(usize),
+    Ciram(usize),
+}
+
+pub struct ChrMemory {
+    chr_mem: Vec<u8>,
+    chr_ram: bool,
+    ciram: [u8; 2 * 1024],
+}
+
+// internal RAM, IRQ counter and wavetable audio, clocked every CPU cycle
+pub struct Namco163 {
+    pub(crate) internal_ram: SharedMut<Vec<u8>>,
+    irq: SharedMut<bool>,
+    irq_counter: u16,
+    irq_enabled: bool,
+    ram_address: u8,
+    auto_increment: bool,
+    sound_enabled: bool,
+    prescaler: u8,
+    channel: usize,
+    outputs: [i16; 8],
+}
+
+pub struct PrgMapper {
+    registers: SharedMut<Registers>,
+    namco163: SharedMut<Namco163>,
+    prg_mem: Vec<u8>,
+    prg_ram: SharedMut<Vec<u8>>,
+}
+
+pub struct ChrMapper {
+    registers: SharedMut<Registers>,
+    memory: SharedMut<ChrMemory>,
+}
+
+pub struct Nametables {
+    registers: SharedMut<Registers>,
+    memory: SharedMut<ChrMemory>,
+}
+
+pub fn new_mapper(
+    cartridge: Cartridge,
+    irq: SharedMut<bool>,
+) -> (PrgMapper, ChrMapper, Nametables, SharedMut<Namco163>) {
+    let registers = Rc::new(RefCell::new(Registers::default()));
+    let namco163 = Rc::new(RefCell::new(Namco163 {
+        internal_ram: Rc::new(RefCell::new(vec![0u8; 128])),
+        irq,
+        irq_counter: 0,
+        irq_enabled: false,
+        ram_address: 0,
+        auto_increment: false,
+        sound_enabled: true,
+        prescaler: 0,
+        channel: 7,
+        outputs: [0; 8],
+    }));
+    let memory = Rc::new(RefCell::new(ChrMemory {
+        chr_ram: cartridge.chr_banks == 0,
+        chr_mem: if cartridge.chr_banks == 0 {
+            vec![0u8; cartridge.chr_ram_size]
+        } else {
+            cartridge.chr_rom
+        },
+        ciram: [0u8; 2 * 1024],
+    }));
+
+    let prg_mapper = PrgMapper {
+        registers: registers.clone(),
+        namco163: namco163.clone(),
+        prg_mem: cartridge.prg_rom,
+        prg_ram: cartridge.prg_ram,
+    };
+    let chr_mapper = ChrMapper {
+        registers: registers.clone(),
+        memory: memory.clone(),
+    };
+    let nametables = Nametables { registers, memory };
+    (prg_mapper, chr_mapper, nametables, namco163)
+}
+
+impl PrgMapper {
+    fn map_address(&self, address: u16) -> usize {
+        let registers = self.registers.borrow();
+        let banks = self.prg_mem.len() / 0x2000;
+
+        // switch 8 KiB at $8000, $A000 and $C000, last bank fixed at $E000
+        let bank = match address / 0x2000 {
+            slot @ 0..=2 => (registers.prg_banks[slot as usize] & 0x3F) as usize,
+            _ => banks - 1,
+        };
+
+        (bank % banks) * 0x2000 + (address & 0x1FFF) as usize
+    }
+
+    // writes need $4X in the upper nibble and the 2 KiB window unprotected in the lower one
+    fn prg_ram_writable(&self, address: u16) -> bool {
+        let protect = self.registers.borrow().ram_protect;
+        protect & 0xF0 == 0x40 && protect & (0x01 << (address / 0x0800)) == 0
+    }
+
+    fn write_register(&mut self, register: u16, data: u8) {
+        let mut registers = self.registers.borrow_mut();
+        match register {
+            0x0..=0x7 => registers.chr_banks[register as usize] = data,
+            0x8..=0xB => registers.nametable_banks[register as usize - 0x8] = data,
+            0xC..=0xE => {
+                registers.prg_banks[register as usize - 0xC] = data;
+                if register == 0xC {
+                    self.namco163.borrow_mut().sound_enabled = data & 0x40 == 0;
+                }
+            }
+            _ => {
+                // also the address port of the internal RAM
+                registers.ram_protect = data;
+                let mut namco163 = self.namco163.borrow_mut();
+                namco163.ram_address = data & 0x7F;
+                namco163.auto_increment = data & 0x80 != 0;
+            }
+        }
+    }
+}
+
+// This interface is exposed for PRG-RAM ($6000-$7FFF) and PRG-ROM ($8000-$FFFF)
+impl Device for PrgMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.prg_ram.borrow()[address as usize],
+            _ => self.prg_mem[self.map_address(address - 0x2000)],
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                if self.prg_ram_writable(address) {
+                    self.prg_ram.borrow_mut()[address as usize] = data;
+                }
+            }
+            // one register every 2 KiB from $8000
+            _ => self.write_register((address - 0x2000) / 0x0800, data),
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        let registers = self.registers.borrow();
+        state.write_bytes(&registers.chr_banks);
+        state.write_bytes(&registers.nametable_banks);
+        state.write_bytes(&registers.prg_banks);
+        state.write_u8(registers.ram_protect);
+        self.namco163.borrow().save_state(state);
+        state.write_bytes(&self.prg_ram.borrow());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut registers = self.registers.borrow_mut();
+        state.read_bytes_into(&mut registers.chr_banks)?;
+        state.read_bytes_into(&mut registers.nametable_banks)?;
+        state.read_bytes_into(&mut registers.prg_banks)?;
+        registers.ram_protect = state.read_u8()?;
+        self.namco163.borrow_mut().load_state(state)?;
+        state.read_bytes_into(&mut self.prg_ram.borrow_mut())
+    }
+}
+
+impl ChrMemory {
+    fn read(&self, page: Page, address: u16) -> u8 {
+        match page {
+            Page::Chr(bank) => {
+                self.chr_mem[(bank * 0x0400 + (address & 0x03FF) as usize) % self.chr_mem.len()]
+            }
+            Page::Ciram(bank) => self.ciram[bank * 0x0400 + (address & 0x03FF) as usize],
+        }
+    }
+
+    fn write(&mut self, page: Page, address: u16, data: u8) {
+        match page {
+            Page::Chr(bank) if self.chr_ram => {
+                let address = (bank * 0x0400 + (address & 0x03FF) as usize) % self.chr_mem.len();
+                self.chr_mem[address] = data;
+            }
+            Page::Chr(_) => (),
+            Page::Ciram(bank) => self.ciram[bank * 0x0400 + (address & 0x03FF) as usize] = data,
+        }
+    }
+}
+
+impl ChrMapper {
+    // banks $E0-$FF select CIRAM unless disabled for that pattern table by $E800
+    fn page(&self, address: u16) -> Page {
+        let registers = self.registers.borrow();
+        let bank = registers.chr_banks[(address / 0x0400) as usize];
+        let ciram_disabled = registers.prg_banks[1] & (0x40 << (address / 0x1000)) != 0;
+        if bank >= 0xE0 && !ciram_disabled {
+            Page::Ciram((bank & 0x01) as usize)
+        } else {
+            Page::Chr(bank as usize)
+        }
+    }
+}
+
+impl Device for ChrMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        self.memory.borrow().read(self.page(address), address)
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        let page = self.page(address);
+        self.memory.borrow_mut().write(page, address, data);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        let memory = self.memory.borrow();
+        if memory.chr_ram {
+            state.write_bytes(&memory.chr_mem);
+        }
+        state.write_bytes(&memory.ciram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut memory = self.memory.borrow_mut();
+        if memory.chr_ram {
+            state.read_bytes_into(&mut memory.chr_mem)?;
+        }
+        state.read_bytes_into(&mut memory.ciram)
+    }
+}
+
+impl Nametables {
+    // banks $E0-$FF select CIRAM, any other bank a CHR page
+    fn page(&self, address: u16) -> Page {
+        let bank = self.registers.borrow().nametable_banks[((address / 0x0400) % 4) as usize];
+        if bank >= 0xE0 {
+            Page::Ciram((bank & 0x01) as usize)
+        } else {
+            Page::Chr(bank as usize)
+        }
+    }
+}
+
+// This interface is exposed for the nametables ($2000-$2FFF on PPU Bus)
+impl Device for Nametables {
+    fn read(&mut self, address: u16) -> u8 {
+        self.memory.borrow().read(self.page(address), address)
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        let page = self.page(address);
+        self.memory.borrow_mut().write(page, address, data);
+    }
+}
+
+impl Namco163 {
+    // channel 7 is always enabled, the others are enabled down from it
+    fn channels(&self) -> usize {
+        ((self.internal_ram.borrow()[0x7F] >> 4) & 0x07) as usize + 1
+    }
+
+    // https://wiki.nesdev.com/w/index.php/Namco_163_audio
+    fn clock_channel(&mut self, channel: usize) {
+        let mut ram = self.internal_ram.borrow_mut();
+        let base = 0x40 + channel * 8;
+
+        let frequency =
+            ram[base] as u32 | (ram[base + 2] as u32) << 8 | ((ram[base + 4] & 0x03) as u32) << 16;
+        let phase =
+            ram[base + 1] as u32 | (ram[base + 3] as u32) << 8 | (ram[base + 5] as u32) << 16;
+        let length = 256 - (ram[base + 4] & 0xFC) as u32;
+
+        let phase = (phase + frequency) % (length << 16);
+        ram[base + 1] = phase as u8;
+        ram[base + 3] = (phase >> 8) as u8;
+        ram[base + 5] = (phase >> 16) as u8;
+
+        // 4-bit samples packed two per byte, low nibble first
+        let sample_address = ((phase >> 16) + ram[base + 6] as u32) as u8;
+        let sample = (ram[sample_address as usize / 2] >> ((sample_address & 0x01) * 4)) & 0x0F;
+        let volume = ram[base + 7] & 0x0F;
+        self.outputs[channel] = (sample as i16 - 8) * volume as i16;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.internal_ram.borrow());
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq_enabled);
+        state.write_u8(self.ram_address);
+        state.write_bool(self.auto_increment);
+        state.write_bool(self.sound_enabled);
+        state.write_u8(self.prescaler);
+        state.write_u8(self.channel as u8);
+        for output in self.outputs {
+            state.write_u16(output as u16);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.internal_ram.borrow_mut())?;
+        self.irq_counter = state.read_u16()? & 0x7FFF;
+        self.irq_enabled = state.read_bool()?;
+        self.ram_address = state.read_u8()? & 0x7F;
+        self.auto_increment = state.read_bool()?;
+        self.sound_enabled = state.read_bool()?;
+        self.prescaler = state.read_u8()? % 15;
+        self.channel = state.read_u8()? as usize;
+        if self.channel >= 8 {
+            return Err(StateError::Corrupted);
+        }
+        for output in self.outputs.iter_mut() {
+            *output = state.read_u16()? as i16;
+        }
+        Ok(())
+    }
+}
+
+// This interface is exposed for the internal RAM port and IRQ counter ($4020-$5FFF)
+impl Device for Namco163 {
+    fn read(&mut self, address: u16) -> u8 {
+        match address + 0x4020 {
+            0x4800..=0x4FFF => {
+                let data = self.internal_ram.borrow()[self.ram_address as usize];
+                if self.auto_increment {
+                    self.ram_address = (self.ram_address + 1) & 0x7F;
+                }
+                data
+            }
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7,
+            _ => 0x00,
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address + 0x4020 {
+            0x4800..=0x4FFF => {
+                self.internal_ram.borrow_mut()[self.ram_address as usize] = data;
+                if self.auto_increment {
+                    self.ram_address = (self.ram_address + 1) & 0x7F;
+                }
+            }
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | data as u16;
+                self.irq.replace(false);
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((data & 0x7F) as u16) << 8;
+                self.irq_enabled = data & 0x80 != 0;
+                self.irq.replace(false);
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Expansion for Namco163 {
+    fn clock(&mut self) {
+        // the counter counts up every CPU cycle and stops at $7FFF, firing the IRQ
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq.replace(true);
+            }
+        }
+
+        // one enabled channel is updated every 15 CPU cycles, from channel 7 down
+        self.prescaler = (self.prescaler + 1) % 15;
+        if self.prescaler == 0 {
+            let channel = self.channel;
+            self.clock_channel(channel);
+            self.channel = if self.channel <= 8 - self.channels() {
+                7
+            } else {
+                self.channel - 1
+            };
+        }
+    }
+
+    // hardware multiplexes the channels, which averages out to their mean
+    fn output(&self) -> f32 {
+        if !self.sound_enabled {
+            return 0.0;
+        }
+        let channels = self.channels();
+        let sum: i16 = self.outputs[8 - channels..].iter().sum();
+        sum as f32 / channels as f32 * OUTPUT_LEVEL
+    }
+}
@@ -0,0 +1,486 @@
+use crate::bus::{Device, SharedMut};
+use crate::cartridge::mappers::Expansion;
+use crate::cartridge::{Cartridge, MirrorMode};
+use crate::state::{StateError, StateReader, StateWriter};
+use std::{cell::RefCell, rc::Rc};
+
+// VRC6 pulse at full volume is about as loud as an APU pulse at full volume
+const OUTPUT_LEVEL: f32 = 0.00996;
+
+// https://wiki.nesdev.com/w/index.php/VRC6
+#[derive(Default)]
+pub struct Registers {
+    prg_bank16: u8,
+    prg_bank8: u8,
+    banking_mode: u8,
+    chr_banks: [u8; 8],
+}
+
+// IRQ counter and expansion audio, clocked every CPU cycle
+pub struct Vrc6 {
+    irq: SharedMut<bool>,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_prescaler: i16,
+    irq_enabled: bool,
+    irq_enable_after_ack: bool,
+    irq_cycle_mode: bool,
+    audio_halt: bool,
+    frequency_shift: u8,
+    pulse1: Pulse,
+    pulse2: Pulse,
+    sawtooth: Sawtooth,
+}
+
+pub struct PrgMapper {
+    registers: SharedMut<Registers>,
+    vrc6: SharedMut<Vrc6>,
+    mirror_mode: SharedMut<MirrorMode>,
+    swap_lines: bool,
+    prg_mem: Vec<u8>,
+    prg_ram: SharedMut<Vec<u8>>,
+}
+
+pub struct ChrMapper {
+    registers: SharedMut<Registers>,
+    chr_mem: Vec<u8>,
+    chr_ram: bool,
+}
+
+pub fn new_mapper(
+    cartridge: Cartridge,
+    mirror_mode: SharedMut<MirrorMode>,
+    irq: SharedMut<bool>,
+) -> (PrgMapper, ChrMapper, SharedMut<Vrc6>) {
+    let registers = Rc::new(RefCell::new(Registers::default()));
+    let vrc6 = Rc::new(RefCell::new(Vrc6 {
+        irq,
+        irq_latch: 0,
+        irq_counter: 0,
+        irq_prescaler: 341,
+        irq_enabled: false,
+        irq_enable_after_ack: false,
+        irq_cycle_mode: false,
+        audio_halt: false,
+        frequency_shift: 0,
+        pulse1: Pulse::default(),
+        pulse2: Pulse::default(),
+        sawtooth: Sawtooth::default(),
+    }));
+
+    let prg_mapper = PrgMapper {
+        registers: registers.clone(),
+        vrc6: vrc6.clone(),
+        mirror_mode,
+        // mapper 26 boards have CPU A0 and A1 wired the other way around
+        swap_lines: cartridge.mapper_id == 26,
+        prg_mem: cartridge.prg_rom,
+        prg_ram: cartridge.prg_ram,
+    };
+    let chr_mapper = ChrMapper {
+        registers,
+        chr_ram: cartridge.chr_banks == 0,
+        chr_mem: if cartridge.chr_banks == 0 {
+            vec![0u8; cartridge.chr_ram_size]
+        } else {
+            cartridge.chr_rom
+        },
+    };
+    (prg_mapper, chr_mapper, vrc6)
+}
+
+impl PrgMapper {
+    fn map_address(&self, address: u16) -> usize {
+        let registers = self.registers.borrow();
+        let banks = self.prg_mem.len() / 0x2000;
+
+        // switch 16 KiB at $8000 and 8 KiB at $C000, last bank fixed at $E000
+        let bank = match address / 0x2000 {
+            0 => (registers.prg_bank16 & 0x0F) as usize * 2,
+            1 => (registers.prg_bank16 & 0x0F) as usize * 2 + 1,
+            2 => (registers.prg_bank8 & 0x1F) as usize,
+            _ => banks - 1,
+        };
+
+        (bank % banks) * 0x2000 + (address & 0x1FFF) as usize
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.registers.borrow().banking_mode & 0x80 != 0
+    }
+
+    fn write_register(&mut self, register: u16, data: u8) {
+        let mut registers = self.registers.borrow_mut();
+        let mut vrc6 = self.vrc6.borrow_mut();
+        match register {
+            0x8000..=0x8003 => registers.prg_bank16 = data,
+            0x9000..=0x9002 => vrc6.pulse1.write(register & 0x03, data),
+            0x9003 => {
+                vrc6.audio_halt = data & 0x01 != 0;
+                vrc6.frequency_shift = if data & 0x04 != 0 {
+                    8
+                } else if data & 0x02 != 0 {
+                    4
+                } else {
+                    0
+                };
+            }
+            0xA000..=0xA002 => vrc6.pulse2.write(register & 0x03, data),
+            0xB000..=0xB002 => vrc6.sawtooth.write(register & 0x03, data),
+            0xB003 => {
+                registers.banking_mode = data;
+                self.mirror_mode.replace(match (data >> 2) & 0x03 {
+                    0 => MirrorMode::Vertical,
+                    1 => MirrorMode::Horizontal,
+                    2 => MirrorMode::SingleScreenLower,
+                    _ => MirrorMode::SingleScreenUpper,
+                });
+            }
+            0xC000..=0xC003 => registers.prg_bank8 = data,
+            0xD000..=0xD003 => registers.chr_banks[(register & 0x03) as usize] = data,
+            0xE000..=0xE003 => registers.chr_banks[4 + (register & 0x03) as usize] = data,
+            0xF000 => vrc6.irq_latch = data,
+            0xF001 => {
+                vrc6.irq_enable_after_ack = data & 0x01 != 0;
+                vrc6.irq_enabled = data & 0x02 != 0;
+                vrc6.irq_cycle_mode = data & 0x04 != 0;
+                if vrc6.irq_enabled {
+                    vrc6.irq_counter = vrc6.irq_latch;
+                    vrc6.irq_prescaler = 341;
+                }
+                vrc6.irq.replace(false);
+            }
+            0xF002 => {
+                vrc6.irq_enabled = vrc6.irq_enable_after_ack;
+                vrc6.irq.replace(false);
+            }
+            _ => (),
+        }
+    }
+}
+
+// This interface is exposed for PRG-RAM ($6000-$7FFF) and PRG-ROM ($8000-$FFFF)
+impl Device for PrgMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF if self.prg_ram_enabled() => self.prg_ram.borrow()[address as usize],
+            0x0000..=0x1FFF => 0x00,
+            _ => self.prg_mem[self.map_address(address - 0x2000)],
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                if self.prg_ram_enabled() {
+                    self.prg_ram.borrow_mut()[address as usize] = data;
+                }
+            }
+            _ => {
+                let register = (address + 0x6000) & 0xF003;
+                let register = if self.swap_lines {
+                    (register & 0xF000) | ((register & 0x01) << 1) | ((register & 0x02) >> 1)
+                } else {
+                    register
+                };
+                self.write_register(register, data);
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        let registers = self.registers.borrow();
+        state.write_u8(registers.prg_bank16);
+        state.write_u8(registers.prg_bank8);
+        state.write_u8(registers.banking_mode);
+        state.write_bytes(&registers.chr_banks);
+        self.vrc6.borrow().save_state(state);
+        state.write_bytes(&self.prg_ram.borrow());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut registers = self.registers.borrow_mut();
+        registers.prg_bank16 = state.read_u8()?;
+        registers.prg_bank8 = state.read_u8()?;
+        registers.banking_mode = state.read_u8()?;
+        state.read_bytes_into(&mut registers.chr_banks)?;
+        self.vrc6.borrow_mut().load_state(state)?;
+        state.read_bytes_into(&mut self.prg_ram.borrow_mut())
+    }
+}
+
+impl ChrMapper {
+    fn map_address(&self, address: u16) -> usize {
+        let registers = self.registers.borrow();
+        let r = &registers.chr_banks;
+        let slot = (address / 0x0400) as usize;
+
+        // in the 2 KiB modes bit 5 makes PPU A10 pick the half of the bank
+        let (and, or) = if registers.banking_mode & 0x20 != 0 {
+            (0xFE, 0x01)
+        } else {
+            (0xFF, 0x00)
+        };
+        let half = |bank: u8| {
+            if slot & 0x01 == 0 {
+                bank & and
+            } else {
+                bank | or
+            }
+        };
+        let bank = match (registers.banking_mode & 0x03, slot) {
+            // four 2 KiB banks
+            (1, _) => half(r[slot / 2]),
+            // eight 1 KiB banks, or four 1 KiB banks at $0000 in the mixed modes
+            (0, _) | (_, 0..=3) => r[slot],
+            // two 2 KiB banks at $1000 in the mixed modes
+            _ => half(r[2 + slot / 2]),
+        } as usize;
+
+        (bank * 0x0400 + (address & 0x03FF) as usize) % self.chr_mem.len()
+    }
+}
+
+impl Device for ChrMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        self.chr_mem[self.map_address(address)]
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if self.chr_ram {
+            let address = self.map_address(address);
+            self.chr_mem[address] = data;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        if self.chr_ram {
+            state.write_bytes(&self.chr_mem);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if self.chr_ram {
+            state.read_bytes_into(&mut self.chr_mem)?;
+        }
+        Ok(())
+    }
+}
+
+impl Vrc6 {
+    // https://wiki.nesdev.com/w/index.php/VRC_IRQ
+    fn clock_irq(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+
+        // scanline mode divides CPU cycles by 113.667 with a prescaler
+        if !self.irq_cycle_mode {
+            self.irq_prescaler -= 3;
+            if self.irq_prescaler > 0 {
+                return;
+            }
+            self.irq_prescaler += 341;
+        }
+
+        if self.irq_counter == 0xFF {
+            self.irq_counter = self.irq_latch;
+            self.irq.replace(true);
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.irq_latch);
+        state.write_u8(self.irq_counter);
+        state.write_u16(self.irq_prescaler as u16);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_enable_after_ack);
+        state.write_bool(self.irq_cycle_mode);
+        state.write_bool(self.audio_halt);
+        state.write_u8(self.frequency_shift);
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        self.sawtooth.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.irq_latch = state.read_u8()?;
+        self.irq_counter = state.read_u8()?;
+        self.irq_prescaler = state.read_u16()? as i16;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_enable_after_ack = state.read_bool()?;
+        self.irq_cycle_mode = state.read_bool()?;
+        self.audio_halt = state.read_bool()?;
+        self.frequency_shift = state.read_u8()?;
+        self.pulse1.load_state(state)?;
+        self.pulse2.load_state(state)?;
+        self.sawtooth.load_state(state)?;
+        Ok(())
+    }
+}
+
+impl Expansion for Vrc6 {
+    fn clock(&mut self) {
+        self.clock_irq();
+        if !self.audio_halt {
+            self.pulse1.clock(self.frequency_shift);
+            self.pulse2.clock(self.frequency_shift);
+            self.sawtooth.clock(self.frequency_shift);
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let output = self.pulse1.output() + self.pulse2.output() + self.sawtooth.output();
+        output as f32 * OUTPUT_LEVEL
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/VRC6_audio#Pulse_Channels
+#[derive(Default)]
+struct Pulse {
+    enabled: bool,
+    ignore_duty: bool,
+    duty: u8,
+    volume: u8,
+    step: u8,
+    timer: u16,
+    timer_period: u16,
+}
+
+impl Pulse {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0x00 => {
+                self.ignore_duty = data & 0x80 != 0;
+                self.duty = (data >> 4) & 0x07;
+                self.volume = data & 0x0F;
+            }
+            0x01 => self.timer_period = (self.timer_period & 0x0F00) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, frequency_shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.timer_period >> frequency_shift;
+            self.step = if self.step == 0 { 15 } else { self.step - 1 };
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.ignore_duty || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.ignore_duty);
+        state.write_u8(self.duty);
+        state.write_u8(self.volume);
+        state.write_u8(self.step);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.ignore_duty = state.read_bool()?;
+        self.duty = state.read_u8()? & 0x07;
+        self.volume = state.read_u8()? & 0x0F;
+        self.step = state.read_u8()? & 0x0F;
+        self.timer = state.read_u16()?;
+        self.timer_period = state.read_u16()?;
+        Ok(())
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/VRC6_audio#Sawtooth_Channel
+#[derive(Default)]
+struct Sawtooth {
+    enabled: bool,
+    rate: u8,
+    accumulator: u8,
+    step: u8,
+    timer: u16,
+    timer_period: u16,
+}
+
+impl Sawtooth {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0x00 => self.rate = data & 0x3F,
+            0x01 => self.timer_period = (self.timer_period & 0x0F00) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.accumulator = 0;
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    // the accumulator grows by the rate every other step and resets after 7 additions
+    fn clock(&mut self, frequency_shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.timer_period >> frequency_shift;
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step.is_multiple_of(2) {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.rate);
+        state.write_u8(self.accumulator);
+        state.write_u8(self.step);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.rate = state.read_u8()? & 0x3F;
+        self.accumulator = state.read_u8()?;
+        self.step = state.read_u8()?;
+        if self.step >= 14 {
+            return Err(StateError::Corrupted);
+        }
+        self.timer = state.read_u16()?;
+        self.timer_period = state.read_u16()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use crate::bus::{Device, SharedMut};
+use crate::cartridge::Cartridge;
+use crate::state::{StateError, StateReader, StateWriter};
+use std::{cell::RefCell, rc::Rc};
+
+// https://wiki.nesdev.com/w/index.php/GxROM
+// https://wiki.nesdev.com/w/index.php/Color_Dreams (mapper 11)
+// Both boards have a single register that selects a 32 KiB PRG bank and an
+// 8 KiB CHR bank, they only differ in which bits select which, so the register
+// is kept as written and decoded on every access
+pub type BankSelect = fn(u8) -> (usize, usize);
+
+// (PRG bank, CHR bank) of a register write
+pub fn gxrom(data: u8) -> (usize, usize) {
+    (((data >> 4) & 0x03) as usize, (data & 0x03) as usize)
+}
+
+pub fn color_dreams(data: u8) -> (usize, usize) {
+    ((data & 0x03) as usize, (data >> 4) as usize)
+}
+
+pub struct PrgMapper {
+    bank_select: BankSelect,
+    register: SharedMut<u8>,
+    prg_mem: Vec<u8>,
+    prg_ram: SharedMut<Vec<u8>>,
+}
+
+pub struct ChrMapper {
+    bank_select: BankSelect,
+    register: SharedMut<u8>,
+    chr_mem: Vec<u8>,
+    chr_ram: bool,
+}
+
+pub fn new_mapper(cartridge: Cartridge, bank_select: BankSelect) -> (PrgMapper, ChrMapper) {
+    let register = Rc::new(RefCell::new(0));
+
+    let prg_mapper = PrgMapper {
+        bank_select,
+        register: register.clone(),
+        prg_mem: cartridge.prg_rom,
+        prg_ram: cartridge.prg_ram,
+    };
+    let chr_mapper = ChrMapper {
+        bank_select,
+        register,
+        chr_ram: cartridge.chr_banks == 0,
+        chr_mem: if cartridge.chr_banks == 0 {
+            vec![0u8; cartridge.chr_ram_size]
+        } else {
+            cartridge.chr_rom
+        },
+    };
+    (prg_mapper, chr_mapper)
+}
+
+impl PrgMapper {
+    fn map_address(&self, address: u16) -> usize {
+        // switch 32 KiB at $8000
+        let (prg_bank, _) = (self.bank_select)(*self.register.borrow());
+        (prg_bank * 0x8000 + address as usize) % self.prg_mem.len()
+    }
+}
+
+// This interface is exposed for PRG-RAM ($6000-$7FFF) and PRG-ROM ($8000-$FFFF)
+impl Device for PrgMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.prg_ram.borrow()[address as usize],
+            _ => self.prg_mem[self.map_address(address - 0x2000)],
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x0000..=0x1FFF => self.prg_ram.borrow_mut()[address as usize] = data,
+            _ => {
+                self.register.replace(data);
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(*self.register.borrow());
+        state.write_bytes(&self.prg_ram.borrow());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        // every value is a valid register write
+        self.register.replace(state.read_u8()?);
+        state.read_bytes_into(&mut self.prg_ram.borrow_mut())
+    }
+}
+
+impl ChrMapper {
+    fn map_address(&self, address: u16) -> usize {
+        // switch 8 KiB at $0000
+        let (_, chr_bank) = (self.bank_select)(*self.register.borrow());
+        (chr_bank * 0x2000 + address as usize) % self.chr_mem.len()
+    }
+}
+
+impl Device for ChrMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        self.chr_mem[self.map_address(address)]
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if self.chr_ram {
+            let address = self.map_address(address);
+            self.chr_mem[address] = data;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        if self.chr_ram {
+            state.write_bytes(&self.chr_mem);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if self.chr_ram {
+            state.read_bytes_into(&mut self.chr_mem)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,443 @@
+use crate::bus::{Device, SharedMut};
+use crate::cartridge::mappers::Expansion;
+use crate::cartridge::{Cartridge, MirrorMode};
+use crate::state::{StateError, StateReader, StateWriter};
+use std::{cell::RefCell, rc::Rc};
+
+// one 5B channel at full volume is about as loud as an APU pulse at full volume
+const OUTPUT_LEVEL: f32 = 0.15;
+
+// https://wiki.nesdev.com/w/index.php/Sunsoft_FME-7
+#[derive(Default)]
+pub struct Registers {
+    command: u8,
+    prg_banks: [u8; 4],
+    chr_banks: [u8; 8],
+}
+
+// IRQ counter and expansion audio, clocked every CPU cycle
+pub struct Fme7 {
+    irq: SharedMut<bool>,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    audio_register: u8,
+    audio: Sunsoft5b,
+}
+
+pub struct PrgMapper {
+    registers: SharedMut<Registers>,
+    fme7: SharedMut<Fme7>,
+    mirror_mode: SharedMut<MirrorMode>,
+    prg_mem: Vec<u8>,
+    prg_ram: SharedMut<Vec<u8>>,
+}
+
+pub struct ChrMapper {
+    registers: SharedMut<Registers>,
+    chr_mem: Vec<u8>,
+    chr_ram: bool,
+}
+
+pub fn new_mapper(
+    cartridge: Cartridge,
+    mirror_mode: SharedMut<MirrorMode>,
+    irq: SharedMut<bool>,
+) -> (PrgMapper, ChrMapper, SharedMut<Fme7>) {
+    let registers = Rc::new(RefCell::new(Registers::default()));
+    let fme7 = Rc::new(RefCell::new(Fme7 {
+        irq,
+        irq_counter: 0,
+        irq_enabled: false,
+        irq_counter_enabled: false,
+        audio_register: 0,
+        audio: Sunsoft5b::new(),
+    }));
+
+    let prg_mapper = PrgMapper {
+        registers: registers.clone(),
+        fme7: fme7.clone(),
+        mirror_mode,
+        prg_mem: cartridge.prg_rom,
+        prg_ram: cartridge.prg_ram,
+    };
+    let chr_mapper = ChrMapper {
+        registers,
+        chr_ram: cartridge.chr_banks == 0,
+        chr_mem: if cartridge.chr_banks == 0 {
+            vec![0u8; cartridge.chr_ram_size]
+        } else {
+            cartridge.chr_rom
+        },
+    };
+    (prg_mapper, chr_mapper, fme7)
+}
+
+impl PrgMapper {
+    fn map_address(&self, address: u16) -> usize {
+        let registers = self.registers.borrow();
+        let banks = self.prg_mem.len() / 0x2000;
+
+        // switch 8 KiB at $8000, $A000 and $C000, last bank fixed at $E000
+        let bank = match address / 0x2000 {
+            slot @ 0..=2 => (registers.prg_banks[slot as usize + 1] & 0x3F) as usize,
+            _ => banks - 1,
+        };
+
+        (bank % banks) * 0x2000 + (address & 0x1FFF) as usize
+    }
+
+    // $6000-$7FFF holds either a PRG-ROM bank or PRG-RAM, selected by bit 6
+    fn read_low_bank(&self, address: u16) -> u8 {
+        let bank = self.registers.borrow().prg_banks[0];
+        match (bank & 0x40 != 0, bank & 0x80 != 0) {
+            (false, _) => {
+                let banks = self.prg_mem.len() / 0x2000;
+                let bank = (bank & 0x3F) as usize % banks;
+                self.prg_mem[bank * 0x2000 + address as usize]
+            }
+            (true, true) => {
+                let prg_ram = self.prg_ram.borrow();
+                prg_ram[((bank & 0x3F) as usize * 0x2000 + address as usize) % prg_ram.len()]
+            }
+            (true, false) => 0x00,
+        }
+    }
+
+    fn write_low_bank(&mut self, address: u16, data: u8) {
+        let bank = self.registers.borrow().prg_banks[0];
+        if bank & 0xC0 == 0xC0 {
+            let mut prg_ram = self.prg_ram.borrow_mut();
+            let address = ((bank & 0x3F) as usize * 0x2000 + address as usize) % prg_ram.len();
+            prg_ram[address] = data;
+        }
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        let mut registers = self.registers.borrow_mut();
+        let mut fme7 = self.fme7.borrow_mut();
+        match registers.command {
+            command @ 0x00..=0x07 => registers.chr_banks[command as usize] = data,
+            command @ 0x08..=0x0B => registers.prg_banks[command as usize - 0x08] = data,
+            0x0C => {
+                self.mirror_mode.replace(match data & 0x03 {
+                    0 => MirrorMode::Vertical,
+                    1 => MirrorMode::Horizontal,
+                    2 => MirrorMode::SingleScreenLower,
+                    _ => MirrorMode::SingleScreenUpper,
+                });
+            }
+            0x0D => {
+                fme7.irq_enabled = data & 0x01 != 0;
+                fme7.irq_counter_enabled = data & 0x80 != 0;
+                fme7.irq.replace(false);
+            }
+            0x0E => fme7.irq_counter = (fme7.irq_counter & 0xFF00) | data as u16,
+            _ => fme7.irq_counter = (fme7.irq_counter & 0x00FF) | (data as u16) << 8,
+        }
+    }
+}
+
+// This interface is exposed for PRG-RAM ($6000-$7FFF) and PRG-ROM ($8000-$FFFF)
+impl Device for PrgMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.read_low_bank(address),
+            _ => self.prg_mem[self.map_address(address - 0x2000)],
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x0000..=0x1FFF => self.write_low_bank(address, data),
+            0x2000..=0x3FFF => self.registers.borrow_mut().command = data & 0x0F,
+            0x4000..=0x5FFF => self.write_parameter(data),
+            0x6000..=0x7FFF => self.fme7.borrow_mut().audio_register = data & 0x0F,
+            _ => {
+                let mut fme7 = self.fme7.borrow_mut();
+                let register = fme7.audio_register;
+                fme7.audio.write(register, data);
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        let registers = self.registers.borrow();
+        state.write_u8(registers.command);
+        state.write_bytes(&registers.prg_banks);
+        state.write_bytes(&registers.chr_banks);
+        self.fme7.borrow().save_state(state);
+        state.write_bytes(&self.prg_ram.borrow());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut registers = self.registers.borrow_mut();
+        registers.command = state.read_u8()? & 0x0F;
+        state.read_bytes_into(&mut registers.prg_banks)?;
+        state.read_bytes_into(&mut registers.chr_banks)?;
+        self.fme7.borrow_mut().load_state(state)?;
+        state.read_bytes_into(&mut self.prg_ram.borrow_mut())
+    }
+}
+
+impl ChrMapper {
+    fn map_address(&self, address: u16) -> usize {
+        // eight 1 KiB banks
+        let bank = self.registers.borrow().chr_banks[(address / 0x0400) as usize] as usize;
+        (bank * 0x0400 + (address & 0x03FF) as usize) % self.chr_mem.len()
+    }
+}
+
+impl Device for ChrMapper {
+    fn read(&mut self, address: u16) -> u8 {
+        self.chr_mem[self.map_address(address)]
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if self.chr_ram {
+            let address = self.map_address(address);
+            self.chr_mem[address] = data;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        if self.chr_ram {
+            state.write_bytes(&self.chr_mem);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if self.chr_ram {
+            state.read_bytes_into(&mut self.chr_mem)?;
+        }
+        Ok(())
+    }
+}
+
+impl Fme7 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_counter_enabled);
+        state.write_u8(self.audio_register);
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.irq_counter = state.read_u16()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_counter_enabled = state.read_bool()?;
+        self.audio_register = state.read_u8()? & 0x0F;
+        self.audio.load_state(state)
+    }
+}
+
+impl Expansion for Fme7 {
+    fn clock(&mut self) {
+        // the counter decrements every CPU cycle and fires when it wraps below $0000
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq.replace(true);
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn output(&self) -> f32 {
+        self.audio.output() * OUTPUT_LEVEL
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/Sunsoft_5B_audio
+struct Sunsoft5b {
+    registers: [u8; 16],
+    // 5-bit logarithmic volume, 1.5 dB per step
+    levels: [f32; 32],
+    prescaler: u8,
+    tone_timers: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_timer: u8,
+    noise_half: bool,
+    noise_shift_register: u32,
+    envelope_timer: u16,
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+}
+
+impl Sunsoft5b {
+    fn new() -> Sunsoft5b {
+        let mut levels = [0f32; 32];
+        for (step, level) in levels.iter_mut().enumerate().skip(1) {
+            *level = 10f32.powf(-1.5 * (31 - step) as f32 / 20.0);
+        }
+
+        Sunsoft5b {
+            registers: [0u8; 16],
+            levels,
+            prescaler: 0,
+            tone_timers: [0; 3],
+            tone_outputs: [false; 3],
+            noise_timer: 0,
+            noise_half: false,
+            noise_shift_register: 1,
+            envelope_timer: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: false,
+        }
+    }
+
+    fn write(&mut self, register: u8, data: u8) {
+        self.registers[register as usize] = data;
+        if register == 0x0D {
+            self.envelope_step = 0;
+            self.envelope_attack = data & 0x04 != 0;
+            self.envelope_holding = false;
+        }
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        let period = self.registers[channel * 2] as u16
+            | ((self.registers[channel * 2 + 1] & 0x0F) as u16) << 8;
+        period.max(1)
+    }
+
+    // timers run at CPU clock / 16
+    fn clock(&mut self) {
+        self.prescaler = (self.prescaler + 1) % 16;
+        if self.prescaler != 0 {
+            return;
+        }
+
+        for channel in 0..3 {
+            self.tone_timers[channel] += 1;
+            if self.tone_timers[channel] >= self.tone_period(channel) {
+                self.tone_timers[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        // 17-bit LFSR shifted at half the rate of the noise timer
+        self.noise_timer += 1;
+        if self.noise_timer >= (self.registers[0x06] & 0x1F).max(1) {
+            self.noise_timer = 0;
+            self.noise_half = !self.noise_half;
+            if self.noise_half {
+                let feedback =
+                    (self.noise_shift_register ^ (self.noise_shift_register >> 3)) & 0x01;
+                self.noise_shift_register = (self.noise_shift_register >> 1) | (feedback << 16);
+            }
+        }
+
+        let envelope_period =
+            (self.registers[0x0B] as u16 | (self.registers[0x0C] as u16) << 8).max(1);
+        self.envelope_timer += 1;
+        if self.envelope_timer >= envelope_period {
+            self.envelope_timer = 0;
+            self.clock_envelope();
+        }
+    }
+
+    // shape bits: continue, attack, alternate, hold
+    fn clock_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+
+        self.envelope_step += 1;
+        if self.envelope_step < 32 {
+            return;
+        }
+
+        let shape = self.registers[0x0D];
+        if shape & 0x08 == 0 {
+            // no continue, end silent
+            self.envelope_step = 31;
+            self.envelope_attack = false;
+            self.envelope_holding = true;
+        } else if shape & 0x01 != 0 {
+            // hold the last level, inverted when alternating
+            self.envelope_step = 31;
+            if shape & 0x02 != 0 {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_holding = true;
+        } else {
+            self.envelope_step = 0;
+            if shape & 0x02 != 0 {
+                self.envelope_attack = !self.envelope_attack;
+            }
+        }
+    }
+
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let mixer = self.registers[0x07];
+        let noise = self.noise_shift_register & 0x01 != 0;
+
+        (0..3)
+            .map(|channel| {
+                let tone_on = mixer & (0x01 << channel) != 0 || self.tone_outputs[channel];
+                let noise_on = mixer & (0x08 << channel) != 0 || noise;
+                if !(tone_on && noise_on) {
+                    return 0.0;
+                }
+
+                let volume = self.registers[0x08 + channel];
+                let level = if volume & 0x10 != 0 {
+                    self.envelope_level()
+                } else if volume & 0x0F == 0 {
+                    0
+                } else {
+                    (volume & 0x0F) * 2 + 1
+                };
+                self.levels[level as usize]
+            })
+            .sum()
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.registers);
+        state.write_u8(self.prescaler);
+        for channel in 0..3 {
+            state.write_u16(self.tone_timers[channel]);
+            state.write_bool(self.tone_outputs[channel]);
+        }
+        state.write_u8(self.noise_timer);
+        state.write_bool(self.noise_half);
+        state.write_u32(self.noise_shift_register);
+        state.write_u16(self.envelope_timer);
+        state.write_u8(self.envelope_step);
+        state.write_bool(self.envelope_attack);
+        state.write_bool(self.envelope_holding);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.registers)?;
+        self.prescaler = state.read_u8()? % 16;
+        for channel in 0..3 {
+            self.tone_timers[channel] = state.read_u16()?;
+            self.tone_outputs[channel] = state.read_bool()?;
+        }
+        self.noise_timer = state.read_u8()?;
+        self.noise_half = state.read_bool()?;
+        self.noise_shift_register = state.read_u32()?;
+        self.envelope_timer = state.read_u16()?;
+        self.envelope_step = state.read_u8()?;
+        if self.envelope_step >= 32 {
+            return Err(StateError::Corrupted);
+        }
+        self.envelope_attack = state.read_bool()?;
+        self.envelope_holding = state.read_bool()?;
+        Ok(())
+    }
+}
@@ -1,2 +1,456 @@
 pub mod mapper000;
+pub mod mapper001;
+pub mod mapper002;
 pub mod mapper003;
+pub mod mapper004;
+pub mod mapper005;
+pub mod mapper007;
+pub mod mapper009;
+pub mod mapper010;
+pub mod mapper011;
+pub mod mapper019;
+pub mod mapper024;
+pub mod mapper066;
+pub mod mapper069;
+
+use crate::bus::{Device, Fetch, SharedMut};
+use crate::cartridge::error::LoadError;
+use crate::cartridge::{Cartridge, MirrorMode};
+use crate::ppu::nametables::Nametables;
+use crate::ram::Ram;
+use crate::state::{StateError, StateReader, StateWriter};
+use std::collections::HashMap;
+use std::{cell::RefCell, rc::Rc};
+
+/// Cartridge hardware as the console sees it.
+///
+/// `cpu_read`/`cpu_write` receive CPU addresses in $4020-$FFFF and
+/// `ppu_read`/`ppu_write` PPU addresses in $0000-$2FFF, so the mapper serves
+/// both the pattern tables and the nametables (mirroring the console CIRAM as
+/// it sees fit).
+pub trait Mapper {
+    fn cpu_read(&mut self, address: u16) -> u8;
+    fn cpu_write(&mut self, address: u16, data: u8);
+    fn ppu_read(&mut self, address: u16) -> u8;
+    fn ppu_write(&mut self, address: u16, data: u8);
+
+    /// PPU reads while rendering, which tell background and sprite fetches apart
+    fn ppu_fetch(&mut self, address: u16, _fetch: Fetch) -> u8 {
+        self.ppu_read(address)
+    }
+
+    /// Called once every CPU cycle
+    fn clock(&mut self) {}
+
+    /// Whether the cartridge is asserting the CPU IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Expansion audio level, mixed with the APU output
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    /// Memory kept by the cartridge battery between sessions
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn load_battery_ram(&mut self, _data: &[u8]) {}
+
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
+}
+
+/// Builds a mapper for a cartridge, or rejects it (e.g. unsupported sizes)
+pub type MapperConstructor = fn(Cartridge) -> Result<Box<dyn Mapper>, LoadError>;
+
+// Mapper constructors by iNES mapper number and, optionally, submapper
+pub(crate) struct MapperRegistry {
+    constructors: HashMap<(u16, Option<u8>), MapperConstructor>,
+}
+
+impl MapperRegistry {
+    pub fn register(
+        &mut self,
+        mapper_id: u16,
+        submapper_id: Option<u8>,
+        constructor: MapperConstructor,
+    ) {
+        self.constructors
+            .insert((mapper_id, submapper_id), constructor);
+    }
+
+    // a constructor for the exact submapper wins over one for the whole mapper
+    pub fn create(&self, cartridge: Cartridge) -> Result<Box<dyn Mapper>, LoadError> {
+        let mapper_id = cartridge.info.mapper_id;
+        let submapper_id = cartridge.info.submapper_id;
+        let constructor = self
+            .constructors
+            .get(&(mapper_id, Some(submapper_id)))
+            .or_else(|| self.constructors.get(&(mapper_id, None)))
+            .ok_or(LoadError::UnsupportedMapper {
+                mapper_id,
+                submapper_id,
+            })?;
+        constructor(cartridge)
+    }
+}
+
+impl Default for MapperRegistry {
+    fn default() -> MapperRegistry {
+        let mut registry = MapperRegistry {
+            constructors: HashMap::new(),
+        };
+
+        registry.register(0, None, |cartridge| {
+            let hooks = Hooks::new(&cartridge);
+            Ok(Board::new(hooks, mapper000::new_mapper(cartridge)).boxed())
+        });
+        registry.register(1, None, |cartridge| {
+            let hooks = Hooks::new(&cartridge);
+            let (prg_mapper, chr_mapper, registers) =
+                mapper001::new_mapper(cartridge, hooks.mirror_mode.clone());
+            Ok(Board::new(hooks, (prg_mapper, chr_mapper))
+                .with_chip(registers)
+                .boxed())
+        });
+        registry.register(2, None, |cartridge| {
+            let hooks = Hooks::new(&cartridge);
+            let mirror_mode = hooks.mirror_mode.clone();
+            let mapper = mapper002::new_mapper(cartridge, mirror_mode, mapper002::Variant::UxRom);
+            Ok(Board::new(hooks, mapper).boxed())
+        });
+        registry.register(3, None, |cartridge| {
+            let hooks = Hooks::new(&cartridge);
+            Ok(Board::new(hooks, mapper003::new_mapper(cartridge)).boxed())
+        });
+        registry.register(4, None, |cartridge| {
+            let hooks = Hooks::new(&cartridge);
+            let (mirror_mode, irq) = (hooks.mirror_mode.clone(), hooks.irq.clone());
+            let mapper = mapper004::new_mapper(cartridge, mirror_mode, irq);
+            Ok(Board::new(hooks, mapper).boxed())
+        });
+        registry.register(5, None, |cartridge| {
+            let hooks = Hooks::new(&cartridge);
+            let (prg_mapper, chr_mapper, nametables, mmc5) =
+                mapper005::new_mapper(cartridge, hooks.irq.clone());
+            Ok(Board::new(hooks, (prg_mapper, chr_mapper))
+                .with_chip(mmc5.clone())
+                .with_expansion_area(mmc5)
+                .with_nametables(nametables)
+                .boxed())
+        });
+        registry.register(7, None, |cartridge| {
+            let hooks = Hooks::new(&cartridge);
+            let mirror_mode = hooks.mirror_mode.clone();
+            let mapper = mapper007::new_mapper(cartridge, mirror_mode);
+            Ok(Board::new(hooks, mapper).boxed())
+        });
+        registry.register(9, None, |cartridge| {
+            let hooks = Hooks::new(&cartridge);
+            let mirror_mode = hooks.mirror_mode.clone();
+            let mapper = mapper009::new_mapper(cartridge, mirror_mode, mapper009::Chip::Mmc2);
+            Ok(Board::new(hooks, mapper).boxed())
+        });
+        registry.register(10, None, |cartridge| {
+            let hooks = Hooks::new(&cartridge);
+            let mirror_mode = hooks.mirror_mode.clone();
+            let mapper = mapper010::new_mapper(cartridge, mirror_mode);
+            Ok(Board::new(hooks, mapper).boxed())
+        });
+        registry.register(11, None, |cartridge| {
+            let hooks = Hooks::new(&cartridge);
+            let mapper = mapper011::new_mapper(cartridge);
+            Ok(Board::new(hooks, mapper).boxed())
+        });
+        registry.register(19, None, |cartridge| {
+            let hooks = Hooks::new(&cartridge);
+            let (prg_mapper, chr_mapper, nametables, namco163) =
+                mapper019::new_mapper(cartridge, hooks.irq.clone());
+            let internal_ram = namco163.borrow().internal_ram.clone();
+            Ok(Board::new(hooks, (prg_mapper, chr_mapper))
+                .with_chip(namco163.clone())
+                .with_expansion_area(namco163)
+                .with_nametables(nametables)
+                .with_battery_ram(internal_ram)
+                .boxed())
+        });
+        for mapper_id in [24, 26] {
+            registry.register(mapper_id, None, |cartridge| {
+                let hooks = Hooks::new(&cartridge);
+                let (mirror_mode, irq) = (hooks.mirror_mode.clone(), hooks.irq.clone());
+                let (prg_mapper, chr_mapper, vrc6) =
+                    mapper024::new_mapper(cartridge, mirror_mode, irq);
+                Ok(Board::new(hooks, (prg_mapper, chr_mapper))
+                    .with_chip(vrc6)
+                    .boxed())
+            });
+        }
+        registry.register(66, None, |cartridge| {
+            let hooks = Hooks::new(&cartridge);
+            let mapper = mapper066::new_mapper(cartridge, mapper066::gxrom);
+            Ok(Board::new(hooks, mapper).boxed())
+        });
+        registry.register(69, None, |cartridge| {
+            let hooks = Hooks::new(&cartridge);
+            let (mirror_mode, irq) = (hooks.mirror_mode.clone(), hooks.irq.clone());
+            let (prg_mapper, chr_mapper, fme7) = mapper069::new_mapper(cartridge, mirror_mode, irq);
+            Ok(Board::new(hooks, (prg_mapper, chr_mapper))
+                .with_chip(fme7)
+                .boxed())
+        });
+
+        registry
+    }
+}
+
+// Cartridge hardware that runs alongside the CPU, such as cycle based IRQ
+// counters and expansion audio
+pub trait Expansion {
+    fn clock(&mut self);
+
+    // current expansion audio level, mixed with the APU output
+    fn output(&self) -> f32 {
+        0.0
+    }
+}
+
+// State the built-in mappers share with the board around them
+struct Hooks {
+    irq: SharedMut<bool>,
+    mirror_mode: SharedMut<MirrorMode>,
+    battery: bool,
+    battery_ram: Vec<SharedMut<Vec<u8>>>,
+}
+
+impl Hooks {
+    fn new(cartridge: &Cartridge) -> Hooks {
+        Hooks {
+            irq: Rc::new(RefCell::new(false)),
+            // mappers that control mirroring may override the header mode
+            mirror_mode: Rc::new(RefCell::new(cartridge.mirror)),
+            battery: cartridge.battery,
+            battery_ram: cartridge
+                .battery
+                .then(|| cartridge.prg_ram.clone())
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+// Built-in mappers are split into the devices they expose on each bus, this
+// puts them back together behind the Mapper trait
+struct Board {
+    hooks: Hooks,
+    chip: Option<SharedMut<dyn Expansion>>,
+    // (cartridge expansion and others)
+    expansion_area: Box<dyn Device>,
+    prg_mapper: Box<dyn Device>,
+    chr_mapper: Box<dyn Device>,
+    nametables: Box<dyn Device>,
+}
+
+impl Board {
+    fn new(
+        hooks: Hooks,
+        (prg_mapper, chr_mapper): (impl Device + 'static, impl Device + 'static),
+    ) -> Board {
+        let nametables = Nametables::new(hooks.mirror_mode.clone());
+        Board {
+            hooks,
+            chip: None,
+            expansion_area: Box::new(Ram::new(vec![0u8; 8 * 1024])),
+            prg_mapper: Box::new(prg_mapper),
+            chr_mapper: Box::new(chr_mapper),
+            nametables: Box::new(nametables),
+        }
+    }
+
+    fn with_chip(mut self, chip: SharedMut<dyn Expansion>) -> Board {
+        self.chip = Some(chip);
+        self
+    }
+
+    // for mappers with registers below $6000
+    fn with_expansion_area(mut self, expansion_area: impl Device + 'static) -> Board {
+        self.expansion_area = Box::new(expansion_area);
+        self
+    }
+
+    // for mappers with their own nametable memory in place of CIRAM
+    fn with_nametables(mut self, nametables: impl Device + 'static) -> Board {
+        self.nametables = Box::new(nametables);
+        self
+    }
+
+    // saved after the PRG-RAM if the cartridge has a battery
+    fn with_battery_ram(mut self, ram: SharedMut<Vec<u8>>) -> Board {
+        if self.hooks.battery {
+            self.hooks.battery_ram.push(ram);
+        }
+        self
+    }
+
+    fn boxed(self) -> Box<dyn Mapper> {
+        Box::new(self)
+    }
+}
+
+impl Mapper for Board {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x4020..=0x5FFF => self.expansion_area.read(address - 0x4020),
+            _ => self.prg_mapper.read(address - 0x6000),
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x4020..=0x5FFF => self.expansion_area.write(address - 0x4020, data),
+            _ => self.prg_mapper.write(address - 0x6000, data),
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.chr_mapper.read(address),
+            _ => self.nametables.read(address - 0x2000),
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x0000..=0x1FFF => self.chr_mapper.write(address, data),
+            _ => self.nametables.write(address - 0x2000, data),
+        }
+    }
+
+    fn ppu_fetch(&mut self, address: u16, fetch: Fetch) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.chr_mapper.fetch(address, fetch),
+            _ => self.nametables.fetch(address - 0x2000, fetch),
+        }
+    }
+
+    fn clock(&mut self) {
+        if let Some(chip) = &self.chip {
+            chip.borrow_mut().clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        *self.hooks.irq.borrow()
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.chip
+            .as_ref()
+            .map_or(0.0, |chip| chip.borrow().output())
+    }
+
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        if self.hooks.battery_ram.is_empty() {
+            return None;
+        }
+        Some(
+            self.hooks
+                .battery_ram
+                .iter()
+                .flat_map(|ram| ram.borrow().clone())
+                .collect(),
+        )
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        let mut data = data;
+        for ram in &self.hooks.battery_ram {
+            let mut ram = ram.borrow_mut();
+            let len = data.len().min(ram.len());
+            ram[..len].copy_from_slice(&data[..len]);
+            data = &data[len..];
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(*self.hooks.irq.borrow());
+        state.write_u8(match *self.hooks.mirror_mode.borrow() {
+            MirrorMode::Horizontal => 0,
+            MirrorMode::Vertical => 1,
+            MirrorMode::SingleScreenLower => 2,
+            MirrorMode::SingleScreenUpper => 3,
+            MirrorMode::FourScreen => 4,
+        });
+        self.expansion_area.save_state(state);
+        self.prg_mapper.save_state(state);
+        self.chr_mapper.save_state(state);
+        self.nametables.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.hooks.irq.replace(state.read_bool()?);
+        self.hooks.mirror_mode.replace(match state.read_u8()? {
+            0 => MirrorMode::Horizontal,
+            1 => MirrorMode::Vertical,
+            2 => MirrorMode::SingleScreenLower,
+            3 => MirrorMode::SingleScreenUpper,
+            4 => MirrorMode::FourScreen,
+            _ => return Err(StateError::Corrupted),
+        });
+        self.expansion_area.load_state(state)?;
+        self.prg_mapper.load_state(state)?;
+        self.chr_mapper.load_state(state)?;
+        self.nametables.load_state(state)
+    }
+}
+
+// The cartridge slot, empty until a ROM is loaded
+pub(crate) type Slot = SharedMut<Option<Box<dyn Mapper>>>;
+
+// This interface is exposed for the cartridge ($4020-$FFFF on CPU Bus)
+pub(crate) struct CpuPort(pub(crate) Slot);
+
+impl Device for CpuPort {
+    fn read(&mut self, address: u16) -> u8 {
+        match self.0.borrow_mut().as_mut() {
+            Some(mapper) => mapper.cpu_read(address + 0x4020),
+            None => 0x00,
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if let Some(mapper) = self.0.borrow_mut().as_mut() {
+            mapper.cpu_write(address + 0x4020, data);
+        }
+    }
+}
+
+// This interface is exposed for the cartridge ($0000-$2FFF on PPU Bus)
+pub(crate) struct PpuPort(pub(crate) Slot);
+
+impl Device for PpuPort {
+    fn read(&mut self, address: u16) -> u8 {
+        match self.0.borrow_mut().as_mut() {
+            Some(mapper) => mapper.ppu_read(address),
+            None => 0x00,
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if let Some(mapper) = self.0.borrow_mut().as_mut() {
+            mapper.ppu_write(address, data);
+        }
+    }
+
+    fn fetch(&mut self, address: u16, fetch: Fetch) -> u8 {
+        match self.0.borrow_mut().as_mut() {
+            Some(mapper) => mapper.ppu_fetch(address, fetch),
+            None => 0x00,
+        }
+    }
+}
@@ -1,66 +1,137 @@
+pub mod error;
+pub mod info;
 pub mod mappers;
 
+use crate::bus::SharedMut;
+use crate::cartridge::error::LoadError;
+use crate::cartridge::info::{CartridgeInfo, ConsoleType, RomFormat};
+use std::convert::TryFrom;
+use std::{cell::RefCell, rc::Rc};
+
 pub struct Cartridge {
     pub(crate) prg_rom: Vec<u8>,
     pub(crate) chr_rom: Vec<u8>,
     pub(crate) mapper_id: usize,
     pub(crate) prg_banks: usize,
     pub(crate) chr_banks: usize,
+    pub(crate) chr_ram_size: usize,
     pub(crate) mirror: MirrorMode,
+    pub(crate) battery: bool,
+    pub(crate) prg_ram: SharedMut<Vec<u8>>,
+    pub(crate) info: CartridgeInfo,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum MirrorMode {
     Horizontal,
     Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen,
 }
 
 impl Cartridge {
-    pub fn new(rom: &[u8]) -> Cartridge {
-        let nes_signature = &rom[0..4];
-        assert!(
-            nes_signature == [0x4E, 0x45, 0x53, 0x1A],
-            "Not a .NES file (header signature not correct)"
-        );
+    pub fn new(rom: &[u8]) -> Result<Cartridge, LoadError> {
+        let info = CartridgeInfo::try_from(rom)?;
+
+        if let ConsoleType::Extended(console_type) = info.console_type {
+            return Err(LoadError::UnsupportedFormat(format!(
+                "extended console type {}",
+                console_type
+            )));
+        }
+        if info.prg_rom_size == 0 {
+            return Err(LoadError::UnsupportedFormat("no PRG-ROM".to_string()));
+        }
+        // NES 2.0 exponent sizes can describe partial banks, which mappers
+        // can't switch in
+        if info.prg_rom_size % (16 * 1024) != 0 {
+            return Err(LoadError::UnsupportedFormat(format!(
+                "PRG-ROM of {} bytes (not a multiple of 16 KiB)",
+                info.prg_rom_size
+            )));
+        }
+        if info.chr_rom_size % (8 * 1024) != 0 {
+            return Err(LoadError::UnsupportedFormat(format!(
+                "CHR-ROM of {} bytes (not a multiple of 8 KiB)",
+                info.chr_rom_size
+            )));
+        }
 
-        let prg_banks = rom[4] as usize;
-        let chr_banks = rom[5] as usize;
+        let index = 16 + if info.trainer { 512 } else { 0 };
+        let data = rom.get(index..).ok_or(LoadError::TruncatedHeader)?;
 
-        let flag6 = rom[6];
-        let flag7 = rom[7];
+        let prg_len = info.prg_rom_size;
+        let prg_rom = data
+            .get(..prg_len)
+            .ok_or(LoadError::TruncatedPrgRom {
+                expected: prg_len,
+                found: data.len(),
+            })?
+            .to_vec();
 
-        let has_trainer = (flag6 & 0x04) >> 2 == 1;
+        let data = &data[prg_len..];
+        let chr_len = info.chr_rom_size;
+        let chr_rom = data
+            .get(..chr_len)
+            .ok_or(LoadError::TruncatedChrRom {
+                expected: chr_len,
+                found: data.len(),
+            })?
+            .to_vec();
 
-        let mapper_id = (((flag7 >> 4) << 4) | (flag6 >> 4)) as usize;
-        let mirror = if flag6 & 0x01 == 1 {
-            MirrorMode::Vertical
+        // mappers expose at least the 8 KiB window at $6000-$7FFF
+        let prg_ram_len = (info.prg_ram_size + info.prg_nvram_size).max(8 * 1024);
+
+        // boards without CHR-ROM have CHR-RAM, 8 KiB unless a NES 2.0 header says otherwise
+        let declared_chr_ram = info.chr_ram_size + info.chr_nvram_size;
+        let chr_ram_size = if chr_len != 0 {
+            0
+        } else if info.format == RomFormat::Nes20 && declared_chr_ram > 0 {
+            declared_chr_ram
         } else {
-            MirrorMode::Horizontal
+            8 * 1024
         };
 
-        let file_type = if flag7 & 0x0C == 0x08 { 2 } else { 1 };
-        let (prg_rom, chr_rom) = match file_type {
-            1 => {
-                let index = 16 + if has_trainer { 512 } else { 0 };
+        Ok(Cartridge {
+            prg_rom,
+            chr_rom,
+            mapper_id: info.mapper_id as usize,
+            prg_banks: prg_len / (16 * 1024),
+            chr_banks: chr_len / (8 * 1024),
+            chr_ram_size,
+            mirror: info.mirror,
+            battery: info.battery,
+            prg_ram: Rc::new(RefCell::new(vec![0u8; prg_ram_len])),
+            info,
+        })
+    }
+
+    // accessors for mappers implemented outside this crate
 
-                let prg_len = prg_banks * 16 * 1024;
-                let prg_rom = rom[index..index + prg_len].to_vec();
+    pub fn prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
 
-                let chr_len = chr_banks * 8 * 1024;
-                let chr_rom = rom[index + prg_len..index + prg_len + chr_len].to_vec();
+    /// Empty if the board has CHR-RAM instead
+    pub fn chr_rom(&self) -> &[u8] {
+        &self.chr_rom
+    }
 
-                (prg_rom, chr_rom)
-            }
-            _ => panic!("Unknown file type {}", file_type),
-        };
+    pub fn chr_ram_size(&self) -> usize {
+        self.chr_ram_size
+    }
 
-        Cartridge {
-            prg_rom,
-            chr_rom,
-            mapper_id,
-            prg_banks,
-            chr_banks,
-            mirror,
-        }
+    /// Mirroring set by the header (solder pads on the board)
+    pub fn mirror(&self) -> MirrorMode {
+        self.mirror
+    }
+
+    pub fn battery(&self) -> bool {
+        self.battery
+    }
+
+    pub fn info(&self) -> &CartridgeInfo {
+        &self.info
     }
 }
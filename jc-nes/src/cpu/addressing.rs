@@ -1,91 +1,219 @@
-use crate::{bus::Device, cpu::Cpu};
+use crate::{
+    bus::Device,
+    cpu::{Cpu, Operation},
+};
 
-/// Addressing Modes
+/// Addressing Modes, each call runs one cycle of the instruction
+/// (https://www.nesdev.org/6502_cpu.txt)
 impl Cpu {
-    pub(in crate::cpu) fn abs(&mut self) -> u16 {
-        self.pc += 1;
-        let lo = self.bus.read(self.pc);
-        self.pc += 1;
-        let hi = self.bus.read(self.pc);
-        ((hi as u16) << 8) | lo as u16
+    pub(in crate::cpu) fn abs(&mut self) {
+        match self.step {
+            1 => self.address = self.fetch() as u16,
+            2 => {
+                self.address |= (self.fetch() as u16) << 8;
+                if let Operation::Jmp = self.instruction.operation {
+                    self.pc = self.address;
+                    return self.done();
+                }
+            }
+            _ => return self.operate(3),
+        }
+        self.next();
     }
 
-    pub(in crate::cpu) fn absx(&mut self) -> u16 {
-        let address = self.abs();
-        let hi = address & 0xFF00;
-        let address = address.wrapping_add(self.x as u16);
-        self.cycle += (self.extra_cycles && self.page_crossed(hi, address)) as u8;
-        address
+    pub(in crate::cpu) fn absx(&mut self) {
+        self.abs_indexed(self.x);
     }
 
-    pub(in crate::cpu) fn absy(&mut self) -> u16 {
-        let address = self.abs();
-        let hi = address & 0xFF00;
-        let address = address.wrapping_add(self.y as u16);
-        self.cycle += (self.extra_cycles && self.page_crossed(hi, address)) as u8;
-        address
+    pub(in crate::cpu) fn absy(&mut self) {
+        self.abs_indexed(self.y);
     }
 
-    pub(in crate::cpu) fn acc(&mut self) {}
+    pub(in crate::cpu) fn imm(&mut self) {
+        let operand = self.fetch();
+        if let Operation::Read(operation) = self.instruction.operation {
+            operation(self, operand);
+        }
+        self.done();
+    }
 
-    pub(in crate::cpu) fn imm(&mut self) -> u16 {
-        self.pc += 1;
-        self.pc
+    // (also accumulator) the byte after the opcode is read and discarded
+    pub(in crate::cpu) fn imp(&mut self) {
+        self.bus.read(self.pc);
+        if let Operation::Implied(operation) = self.instruction.operation {
+            operation(self);
+        }
+        self.done();
     }
 
-    pub(in crate::cpu) fn imp(&mut self) {}
+    // only used by JMP
+    pub(in crate::cpu) fn ind(&mut self) {
+        match self.step {
+            1 => self.address = self.fetch() as u16,
+            2 => self.address |= (self.fetch() as u16) << 8,
+            3 => self.data = self.bus.read(self.address),
+            _ => {
+                // "ind" is bugged in the original hardware
+                // if the low byte is 0xFF then the high byte should be read from the next page
+                // the bug is that it does not, and instead just wraps around in the same page
+                let hi = (self.address & 0xFF00) | (self.address.wrapping_add(1) & 0x00FF);
+                let pch = self.bus.read(hi);
+                self.pc = ((pch as u16) << 8) | self.data as u16;
+                return self.done();
+            }
+        }
+        self.next();
+    }
 
-    pub(in crate::cpu) fn ind(&mut self) -> u16 {
-        self.pc += 1;
-        let lo = self.bus.read(self.pc);
-        self.pc += 1;
-        let hi = self.bus.read(self.pc);
-        let address = ((hi as u16) << 8) | lo as u16;
+    pub(in crate::cpu) fn indx(&mut self) {
+        match self.step {
+            1 => self.pointer = self.fetch(),
+            2 => {
+                self.bus.read(self.pointer as u16);
+                self.pointer = self.pointer.wrapping_add(self.x);
+            }
+            3 => self.address = self.bus.read(self.pointer as u16) as u16,
+            4 => {
+                let hi = self.bus.read(self.pointer.wrapping_add(1) as u16);
+                self.address |= (hi as u16) << 8;
+            }
+            _ => return self.operate(5),
+        }
+        self.next();
+    }
 
-        // "ind" is bugged in the original hardware
-        // if the low byte is 0xFF then the high byte should be read from the next page
-        // the bug is that it does not, and instead just wraps around in the same page
-        if lo == 0xFF {
-            ((self.bus.read(address & 0xFF00) as u16) << 8) | self.bus.read(address) as u16
-        } else {
-            ((self.bus.read(address + 1) as u16) << 8) | self.bus.read(address) as u16
+    pub(in crate::cpu) fn indy(&mut self) {
+        match self.step {
+            1 => self.pointer = self.fetch(),
+            2 => self.data = self.bus.read(self.pointer as u16),
+            3 => {
+                let hi = self.bus.read(self.pointer.wrapping_add(1) as u16);
+                self.index(self.data, hi, self.y);
+            }
+            4 => return self.fix_page(4),
+            _ => return self.operate(5),
         }
+        self.next();
     }
 
-    pub(in crate::cpu) fn indx(&mut self) -> u16 {
-        self.pc += 1;
-        let address = self.bus.read(self.pc) as u16;
-        let lo = self.bus.read((address + self.x as u16) & 0x00FF);
-        let hi = self.bus.read((address + 1 + self.x as u16) & 0x00FF);
-        ((hi as u16) << 8) + lo as u16
+    // only used by branches
+    pub(in crate::cpu) fn relative(&mut self) {
+        match self.step {
+            1 => {
+                self.data = self.fetch();
+                let Operation::Branch(condition) = self.instruction.operation else {
+                    return self.done();
+                };
+                if !condition(self) {
+                    return self.done();
+                }
+            }
+            2 => {
+                self.bus.read(self.pc);
+                let next = self.pc.wrapping_add(self.data as i8 as u16);
+                if next & 0xFF00 == self.pc & 0xFF00 {
+                    self.pc = next;
+                    return self.done();
+                }
+                // the high byte is fixed in one more cycle
+                self.pc = (self.pc & 0xFF00) | (next & 0x00FF);
+                self.address = next;
+            }
+            _ => {
+                self.bus.read(self.pc);
+                self.pc = self.address;
+                return self.done();
+            }
+        }
+        self.next();
     }
 
-    pub(in crate::cpu) fn indy(&mut self) -> u16 {
-        self.pc += 1;
-        let address = self.bus.read(self.pc) as u16;
-        let lo = self.bus.read(address & 0x00FF) as u16;
-        let hi = self.bus.read((address + 1) & 0x00FF);
-        let hi = (hi as u16) << 8;
-        let address = lo.wrapping_add(hi).wrapping_add(self.y as u16);
-        self.cycle += (self.extra_cycles && self.page_crossed(address, hi)) as u8;
-        address
+    pub(in crate::cpu) fn zp(&mut self) {
+        match self.step {
+            1 => self.address = self.fetch() as u16,
+            _ => return self.operate(2),
+        }
+        self.next();
     }
 
-    pub(in crate::cpu) fn relative(&mut self) -> u16 {
-        self.pc += 1;
-        self.pc
+    pub(in crate::cpu) fn zpx(&mut self) {
+        self.zp_indexed(self.x);
     }
 
-    pub(in crate::cpu) fn zp(&mut self) -> u16 {
-        self.pc += 1;
-        self.bus.read(self.pc) as u16
+    pub(in crate::cpu) fn zpy(&mut self) {
+        self.zp_indexed(self.y);
     }
 
-    pub(in crate::cpu) fn zpx(&mut self) -> u16 {
-        (self.zp() + self.x as u16) & 0xFF
+    fn abs_indexed(&mut self, index: u8) {
+        match self.step {
+            1 => self.data = self.fetch(),
+            2 => {
+                let hi = self.fetch();
+                self.index(self.data, hi, index);
+            }
+            3 => return self.fix_page(3),
+            _ => return self.operate(4),
+        }
+        self.next();
     }
 
-    pub(in crate::cpu) fn zpy(&mut self) -> u16 {
-        (self.zp() + self.y as u16) & 0xFF
+    fn zp_indexed(&mut self, index: u8) {
+        match self.step {
+            1 => self.address = self.fetch() as u16,
+            2 => {
+                self.bus.read(self.address);
+                self.address = (self.address + index as u16) & 0x00FF;
+            }
+            _ => return self.operate(3),
+        }
+        self.next();
+    }
+
+    // the index is added to the low byte first, the carry into the high
+    // byte takes another cycle
+    fn index(&mut self, lo: u8, hi: u8, index: u8) {
+        let (lo, page_crossed) = lo.overflowing_add(index);
+        self.address = ((hi as u16) << 8) | lo as u16;
+        self.page_crossed = page_crossed;
+    }
+
+    // reads from the address before the high byte is fixed, which already is
+    // the operand of reads that stay in the same page
+    fn fix_page(&mut self, step: u8) {
+        if !self.page_crossed && matches!(self.instruction.operation, Operation::Read(_)) {
+            return self.operate(step);
+        }
+        self.bus.read(self.address);
+        if self.page_crossed {
+            self.address = self.address.wrapping_add(0x0100);
+        }
+        self.next();
+    }
+
+    // the cycles once the effective address is known, read-modify-write
+    // instructions write back the unmodified value before the result
+    fn operate(&mut self, start: u8) {
+        match (self.instruction.operation, self.step - start) {
+            (Operation::Read(operation), _) => {
+                let operand = self.bus.read(self.address);
+                operation(self, operand);
+            }
+            (Operation::Write(operation), _) => {
+                let data = operation(self);
+                self.bus.write(self.address, data);
+            }
+            (Operation::Modify(_), 0) => {
+                self.data = self.bus.read(self.address);
+                return self.next();
+            }
+            (Operation::Modify(operation), 1) => {
+                self.bus.write(self.address, self.data);
+                self.data = operation(self, self.data);
+                return self.next();
+            }
+            (Operation::Modify(_), _) => self.bus.write(self.address, self.data),
+            _ => (),
+        }
+        self.done();
     }
 }
@@ -5,8 +5,7 @@ use crate::{
 
 /// Instructions
 impl Cpu {
-    pub(in crate::cpu) fn adc(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn adc(&mut self, operand: u8) {
         let tmp = self.a as u16 + operand as u16 + self.status.carry as u16;
 
         self.status.carry = tmp > 0xFF;
@@ -19,334 +18,228 @@ impl Cpu {
         self.status.overflow =
             ((!(self.a as u16 ^ operand as u16) & (self.a as u16 ^ tmp)) & 0x0080) >> 7 == 1;
         self.a = tmp as u8;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn and(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn and(&mut self, operand: u8) {
         self.a &= operand;
         self.status.zero = self.a == 0;
         self.status.negative = self.is_negative(self.a);
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn asl_acc(&mut self, _acc: ()) {
-        self.status.carry = self.is_negative(self.a);
-        self.a <<= 1;
-        self.status.zero = self.a == 0;
-        self.status.negative = self.is_negative(self.a);
-        self.pc += 1;
+    pub(in crate::cpu) fn asl_acc(&mut self) {
+        self.a = self.asl(self.a);
     }
 
-    pub(in crate::cpu) fn asl_mem(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn asl(&mut self, operand: u8) -> u8 {
         self.status.carry = self.is_negative(operand);
         let operand = operand << 1;
         self.status.negative = self.is_negative(operand);
         self.status.zero = operand == 0;
-        self.bus.write(address, operand);
-        self.pc += 1;
+        operand
     }
 
-    pub(in crate::cpu) fn bcc(&mut self, address: u16) {
-        let opcode = self.bus.read(address);
-        self.relative_jump(!self.status.carry, opcode as i8);
+    pub(in crate::cpu) fn bcc(&self) -> bool {
+        !self.status.carry
     }
 
-    pub(in crate::cpu) fn bcs(&mut self, address: u16) {
-        let opcode = self.bus.read(address);
-        self.relative_jump(self.status.carry, opcode as i8);
+    pub(in crate::cpu) fn bcs(&self) -> bool {
+        self.status.carry
     }
 
-    pub(in crate::cpu) fn beq(&mut self, address: u16) {
-        let opcode = self.bus.read(address);
-        self.relative_jump(self.status.zero, opcode as i8);
+    pub(in crate::cpu) fn beq(&self) -> bool {
+        self.status.zero
     }
 
-    pub(in crate::cpu) fn bit(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn bit(&mut self, operand: u8) {
         self.status.zero = self.a & operand == 0;
         self.status.negative = self.is_negative(operand);
         self.status.overflow = (operand & 0x40) >> 6 == 1;
-        self.pc += 1;
-    }
-
-    pub(in crate::cpu) fn bmi(&mut self, address: u16) {
-        let opcode = self.bus.read(address);
-        self.relative_jump(self.status.negative, opcode as i8);
     }
 
-    pub(in crate::cpu) fn bne(&mut self, address: u16) {
-        let opcode = self.bus.read(address);
-        self.relative_jump(!self.status.zero, opcode as i8);
+    pub(in crate::cpu) fn bmi(&self) -> bool {
+        self.status.negative
     }
 
-    pub(in crate::cpu) fn bpl(&mut self, address: u16) {
-        let opcode = self.bus.read(address);
-        self.relative_jump(!self.status.negative, opcode as i8);
+    pub(in crate::cpu) fn bne(&self) -> bool {
+        !self.status.zero
     }
 
-    pub(in crate::cpu) fn brk(&mut self, _imp: ()) {
-        self.status.b1 = true;
-        self.pc += 1;
+    pub(in crate::cpu) fn bpl(&self) -> bool {
+        !self.status.negative
     }
 
-    pub(in crate::cpu) fn bvc(&mut self, address: u16) {
-        let opcode = self.bus.read(address);
-        self.relative_jump(!self.status.overflow, opcode as i8);
+    pub(in crate::cpu) fn bvc(&self) -> bool {
+        !self.status.overflow
     }
 
-    pub(in crate::cpu) fn bvs(&mut self, address: u16) {
-        let opcode = self.bus.read(address);
-        self.relative_jump(self.status.overflow, opcode as i8);
+    pub(in crate::cpu) fn bvs(&self) -> bool {
+        self.status.overflow
     }
 
-    pub(in crate::cpu) fn clc(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn clc(&mut self) {
         self.status.carry = false;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn cld(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn cld(&mut self) {
         self.status.decimal = false;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn cli(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn cli(&mut self) {
         self.status.interrupt = false;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn clv(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn clv(&mut self) {
         self.status.overflow = false;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn cmp(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn cmp(&mut self, operand: u8) {
         self.status.carry = self.a >= operand;
         self.status.zero = self.a == operand;
         self.status.negative = (self.a.wrapping_sub(operand) & 0x80) >> 7 == 1;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn cpx(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn cpx(&mut self, operand: u8) {
         self.status.carry = self.x >= operand;
         self.status.zero = self.x == operand;
         self.status.negative = (self.x.wrapping_sub(operand) & 0x80) >> 7 == 1;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn cpy(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn cpy(&mut self, operand: u8) {
         self.status.carry = self.y >= operand;
         self.status.zero = self.y == operand;
         self.status.negative = (self.y.wrapping_sub(operand) & 0x80) >> 7 == 1;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn dec(&mut self, address: u16) {
-        let operand = self.bus.read(address).wrapping_sub(1);
-        self.bus.write(address, operand);
+    pub(in crate::cpu) fn dec(&mut self, operand: u8) -> u8 {
+        let operand = operand.wrapping_sub(1);
         self.status.zero = operand == 0;
         self.status.negative = self.is_negative(operand);
-        self.pc += 1;
+        operand
     }
 
-    pub(in crate::cpu) fn dex(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn dex(&mut self) {
         self.x = self.x.wrapping_sub(1);
         self.status.zero = self.x == 0;
         self.status.negative = (self.x & 0x80) >> 7 == 1;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn dey(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn dey(&mut self) {
         self.y = self.y.wrapping_sub(1);
         self.status.zero = self.y == 0;
         self.status.negative = (self.y & 0x80) >> 7 == 1;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn eor(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn eor(&mut self, operand: u8) {
         self.a ^= operand;
         self.status.zero = self.a == 0;
         self.status.negative = self.is_negative(self.a);
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn inc(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn inc(&mut self, operand: u8) -> u8 {
         let operand = operand.wrapping_add(1);
-        self.bus.write(address, operand);
         self.status.zero = operand == 0;
         self.status.negative = self.is_negative(operand);
-        self.pc += 1;
+        operand
     }
 
-    pub(in crate::cpu) fn inx(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn inx(&mut self) {
         self.x = self.x.wrapping_add(1);
         self.status.zero = self.x == 0;
         self.status.negative = (self.x & 0x80) >> 7 == 1;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn iny(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn iny(&mut self) {
         self.y = self.y.wrapping_add(1);
         self.status.zero = self.y == 0;
         self.status.negative = (self.y & 0x80) >> 7 == 1;
-        self.pc += 1;
-    }
-
-    pub(in crate::cpu) fn jmp(&mut self, address: u16) {
-        self.pc = address;
-    }
-
-    pub(in crate::cpu) fn jsr(&mut self, address: u16) {
-        let pcl = (self.pc & 0xFF) as u8;
-        let pch = (self.pc >> 8) as u8;
-        self.push_stack(pch);
-        self.push_stack(pcl);
-        self.pc = address;
     }
 
-    pub(in crate::cpu) fn lda(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn lda(&mut self, operand: u8) {
         self.a = operand;
         self.status.zero = self.a == 0;
         self.status.negative = self.is_negative(self.a);
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn ldx(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn ldx(&mut self, operand: u8) {
         self.x = operand;
         self.status.zero = self.x == 0;
         self.status.negative = (self.x & 0x80) >> 7 == 1;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn ldy(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn ldy(&mut self, operand: u8) {
         self.y = operand;
         self.status.zero = self.y == 0;
         self.status.negative = (self.y & 0x80) >> 7 == 1;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn lsr_acc(&mut self, _acc: ()) {
-        self.status.carry = self.a & 0x01 == 1;
-        self.a >>= 1;
-        self.status.negative = self.is_negative(self.a);
-        self.status.zero = self.a == 0;
-        self.pc += 1;
+    pub(in crate::cpu) fn lsr_acc(&mut self) {
+        self.a = self.lsr(self.a);
     }
 
-    pub(in crate::cpu) fn lsr_mem(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn lsr(&mut self, operand: u8) -> u8 {
         self.status.carry = operand & 0x01 == 1;
         let operand = operand >> 1;
         self.status.negative = self.is_negative(operand);
         self.status.zero = operand == 0;
-        self.bus.write(address, operand);
-        self.pc += 1;
+        operand
     }
 
-    pub(in crate::cpu) fn nop(&mut self, _imp: ()) {
-        self.pc += 1;
-    }
+    pub(in crate::cpu) fn nop(&mut self) {}
 
-    pub(in crate::cpu) fn ora(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn ora(&mut self, operand: u8) {
         self.a |= operand;
         self.status.zero = self.a == 0;
         self.status.negative = self.is_negative(self.a);
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn pha(&mut self, _imp: ()) {
-        self.push_stack(self.a);
-        self.pc += 1;
+    pub(in crate::cpu) fn pha(&mut self) -> u8 {
+        self.a
     }
 
-    pub(in crate::cpu) fn php(&mut self, _imp: ()) {
-        self.push_stack(u8::from(self.status) | 0x30); // NES quirk, not regular 6502
-        self.pc += 1;
+    pub(in crate::cpu) fn php(&mut self) -> u8 {
+        u8::from(self.status) | 0x30 // NES quirk, not regular 6502
     }
 
-    pub(in crate::cpu) fn pla(&mut self, _imp: ()) {
-        self.a = self.pop_stack();
+    pub(in crate::cpu) fn pla(&mut self, operand: u8) {
+        self.a = operand;
         self.status.zero = self.a == 0;
         self.status.negative = self.is_negative(self.a);
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn plp(&mut self, _imp: ()) {
-        self.status = Status::from((self.pop_stack() & 0xEF) | 0x20); // NES quirk, not regular 6502
-        self.pc += 1;
+    pub(in crate::cpu) fn plp(&mut self, operand: u8) {
+        self.status = Status::from((operand & 0xEF) | 0x20); // NES quirk, not regular 6502
     }
 
-    pub(in crate::cpu) fn rol_acc(&mut self, _imp: ()) {
-        let bit0 = self.status.carry as u8;
-        self.status.carry = self.is_negative(self.a);
-        self.a <<= 1;
-        self.a |= bit0;
-        self.status.zero = self.a == 0;
-        self.status.negative = self.is_negative(self.a);
-        self.pc += 1;
+    pub(in crate::cpu) fn rol_acc(&mut self) {
+        self.a = self.rol(self.a);
     }
 
-    pub(in crate::cpu) fn rol_mem(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn rol(&mut self, operand: u8) -> u8 {
         let bit0 = self.status.carry as u8;
         self.status.carry = self.is_negative(operand);
         let operand = operand << 1;
         let operand = operand | bit0;
-        self.bus.write(address, operand);
         self.status.negative = self.is_negative(operand);
         self.status.zero = operand == 0;
-        self.pc += 1;
+        operand
     }
 
-    pub(in crate::cpu) fn ror_acc(&mut self, _imp: ()) {
-        let bit7 = self.status.carry as u8;
-        self.status.carry = self.a & 0x01 == 1;
-        self.a >>= 1;
-        self.a |= bit7 << 7;
-        self.status.zero = self.a == 0;
-        self.status.negative = self.is_negative(self.a);
-        self.pc += 1;
+    pub(in crate::cpu) fn ror_acc(&mut self) {
+        self.a = self.ror(self.a);
     }
 
-    pub(in crate::cpu) fn ror_mem(&mut self, address: u16) {
-        let operand = self.bus.read(address);
+    pub(in crate::cpu) fn ror(&mut self, operand: u8) -> u8 {
         let bit7 = self.status.carry as u8;
         self.status.carry = operand & 0x01 == 1;
         let operand = operand >> 1;
         let operand = operand | bit7 << 7;
-        self.bus.write(address, operand);
         self.status.negative = self.is_negative(operand);
         self.status.zero = operand == 0;
-        self.pc += 1;
+        operand
     }
 
-    pub(in crate::cpu) fn rti(&mut self, _imp: ()) {
-        self.status = Status::from(self.pop_stack());
-        let pcl = self.pop_stack();
-        let pch = self.pop_stack();
-        self.status.b2 = true;
-        self.pc = ((pch as u16) << 8) | pcl as u16;
-    }
-
-    pub(in crate::cpu) fn rts(&mut self, _imp: ()) {
-        let pcl = self.pop_stack();
-        let pch = self.pop_stack();
-        self.pc = ((pch as u16) << 8) | pcl as u16;
-        self.pc += 1;
-    }
-
-    pub(in crate::cpu) fn sbc(&mut self, address: u16) {
-        let operand = self.bus.read(address) ^ 0xFF; // 2's complement (+1 nulified by 1-C)
+    pub(in crate::cpu) fn sbc(&mut self, operand: u8) {
+        let operand = operand ^ 0xFF; // 2's complement (+1 nulified by 1-C)
 
         // rest is the same as adc
         let tmp = self.a as u16 + operand as u16 + self.status.carry as u16;
@@ -360,130 +253,333 @@ impl Cpu {
         self.status.overflow =
             ((!(self.a as u16 ^ operand as u16) & (self.a as u16 ^ tmp)) & 0x0080) >> 7 == 1;
         self.a = tmp as u8;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn sec(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn sec(&mut self) {
         self.status.carry = true;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn sed(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn sed(&mut self) {
         self.status.decimal = true;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn sei(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn sei(&mut self) {
         self.status.interrupt = true;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn sta(&mut self, address: u16) {
-        self.bus.write(address, self.a);
-        self.pc += 1;
+    pub(in crate::cpu) fn sta(&mut self) -> u8 {
+        self.a
     }
 
-    pub(in crate::cpu) fn stx(&mut self, address: u16) {
-        self.bus.write(address, self.x);
-        self.pc += 1;
+    pub(in crate::cpu) fn stx(&mut self) -> u8 {
+        self.x
     }
 
-    pub(in crate::cpu) fn sty(&mut self, address: u16) {
-        self.bus.write(address, self.y);
-        self.pc += 1;
+    pub(in crate::cpu) fn sty(&mut self) -> u8 {
+        self.y
     }
 
-    pub(in crate::cpu) fn tax(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn tax(&mut self) {
         self.x = self.a;
         self.status.zero = self.x == 0;
         self.status.negative = (self.x & 0x80) >> 7 == 1;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn tay(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn tay(&mut self) {
         self.y = self.a;
         self.status.zero = self.y == 0;
         self.status.negative = (self.y & 0x80) >> 7 == 1;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn tsx(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn tsx(&mut self) {
         self.x = self.sp;
         self.status.zero = self.x == 0;
         self.status.negative = (self.x & 0x80) >> 7 == 1;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn txa(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn txa(&mut self) {
         self.a = self.x;
         self.status.zero = self.a == 0;
         self.status.negative = self.is_negative(self.a);
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn txs(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn txs(&mut self) {
         self.sp = self.x;
-        self.pc += 1;
     }
 
-    pub(in crate::cpu) fn tya(&mut self, _imp: ()) {
+    pub(in crate::cpu) fn tya(&mut self) {
         self.a = self.y;
         self.status.zero = self.a == 0;
         self.status.negative = self.is_negative(self.a);
-        self.pc += 1;
+    }
+}
+
+/// Stack and control flow instructions, each call runs one cycle
+/// (https://www.nesdev.org/6502_cpu.txt)
+impl Cpu {
+    // also runs IRQ, NMI and reset, which replace the opcode with BRK
+    pub(in crate::cpu) fn brk(&mut self) {
+        match self.step {
+            // BRK skips a padding byte, interrupts return to the instruction they replaced
+            1 => {
+                self.bus.read(self.pc);
+                if !self.hardware_interrupt {
+                    self.pc = self.pc.wrapping_add(1);
+                }
+            }
+            2 => self.push_stack((self.pc >> 8) as u8),
+            3 => self.push_stack(self.pc as u8),
+            4 => {
+                let brk = if self.hardware_interrupt { 0x00 } else { 0x10 };
+                self.push_stack((u8::from(self.status) & 0xEF) | 0x20 | brk);
+            }
+            5 => {
+                // an NMI that arrives before the vector is read takes over
+                // the sequence of a BRK or IRQ
+                self.address = if self.resetting {
+                    0xFFFC
+                } else if self.nmi_pending {
+                    self.nmi_pending = false;
+                    0xFFFA
+                } else {
+                    0xFFFE
+                };
+                self.data = self.bus.read(self.address);
+                self.status.interrupt = true;
+            }
+            _ => {
+                let pch = self.bus.read(self.address + 1);
+                self.pc = ((pch as u16) << 8) | self.data as u16;
+                self.hardware_interrupt = false;
+                self.resetting = false;
+                return self.done();
+            }
+        }
+        self.next();
+    }
+
+    pub(in crate::cpu) fn jsr(&mut self) {
+        match self.step {
+            1 => self.data = self.fetch(),
+            2 => {
+                self.peek_stack();
+            }
+            3 => self.push_stack((self.pc >> 8) as u8),
+            4 => self.push_stack(self.pc as u8),
+            _ => {
+                let pch = self.bus.read(self.pc);
+                self.pc = ((pch as u16) << 8) | self.data as u16;
+                return self.done();
+            }
+        }
+        self.next();
+    }
+
+    pub(in crate::cpu) fn rti(&mut self) {
+        match self.step {
+            1 => {
+                self.bus.read(self.pc);
+            }
+            2 => {
+                self.peek_stack();
+                self.sp = self.sp.wrapping_add(1);
+            }
+            3 => {
+                let status = self.peek_stack();
+                self.plp(status);
+                self.sp = self.sp.wrapping_add(1);
+            }
+            4 => {
+                self.data = self.peek_stack();
+                self.sp = self.sp.wrapping_add(1);
+            }
+            _ => {
+                let pch = self.peek_stack();
+                self.pc = ((pch as u16) << 8) | self.data as u16;
+                return self.done();
+            }
+        }
+        self.next();
+    }
+
+    pub(in crate::cpu) fn rts(&mut self) {
+        match self.step {
+            1 => {
+                self.bus.read(self.pc);
+            }
+            2 => {
+                self.peek_stack();
+                self.sp = self.sp.wrapping_add(1);
+            }
+            3 => {
+                self.data = self.peek_stack();
+                self.sp = self.sp.wrapping_add(1);
+            }
+            4 => {
+                let pch = self.peek_stack();
+                self.pc = ((pch as u16) << 8) | self.data as u16;
+            }
+            _ => {
+                self.fetch();
+                return self.done();
+            }
+        }
+        self.next();
+    }
+
+    pub(in crate::cpu) fn push(&mut self, operation: fn(&mut Cpu) -> u8) {
+        match self.step {
+            1 => {
+                self.bus.read(self.pc);
+                self.next();
+            }
+            _ => {
+                let data = operation(self);
+                self.push_stack(data);
+                self.done();
+            }
+        }
+    }
+
+    pub(in crate::cpu) fn jam(&mut self) {
+        self.bus.read(self.pc);
+        self.jammed = true;
+    }
+
+    pub(in crate::cpu) fn pull(&mut self, operation: fn(&mut Cpu, u8)) {
+        match self.step {
+            1 => {
+                self.bus.read(self.pc);
+                self.next();
+            }
+            2 => {
+                self.peek_stack();
+                self.sp = self.sp.wrapping_add(1);
+                self.next();
+            }
+            _ => {
+                let data = self.peek_stack();
+                operation(self, data);
+                self.done();
+            }
+        }
     }
 }
 
 /// Unofficial instructions
 impl Cpu {
-    pub(in crate::cpu) fn dcp(&mut self, address: u16) {
-        let operand = self.bus.read(address).wrapping_sub(1);
-        self.bus.write(address, operand);
-        self.cmp(address);
+    pub(in crate::cpu) fn dcp(&mut self, operand: u8) -> u8 {
+        let operand = operand.wrapping_sub(1);
+        self.cmp(operand);
+        operand
+    }
+
+    pub(in crate::cpu) fn isc(&mut self, operand: u8) -> u8 {
+        let operand = operand.wrapping_add(1);
+        self.sbc(operand);
+        operand
+    }
+
+    pub(in crate::cpu) fn ahx(&mut self) -> u8 {
+        self.unstable_store(self.a & self.x)
+    }
+
+    pub(in crate::cpu) fn alr(&mut self, operand: u8) {
+        self.and(operand);
+        self.lsr_acc();
+    }
+
+    pub(in crate::cpu) fn anc(&mut self, operand: u8) {
+        self.and(operand);
+        self.status.carry = self.status.negative;
+    }
+
+    pub(in crate::cpu) fn arr(&mut self, operand: u8) {
+        self.and(operand);
+        self.ror_acc();
+        // C and V come from bits 6 and 5 of the result, as if added
+        self.status.carry = self.a & 0x40 != 0;
+        self.status.overflow = ((self.a >> 6) ^ (self.a >> 5)) & 0x01 == 1;
+    }
+
+    pub(in crate::cpu) fn axs(&mut self, operand: u8) {
+        let operand_ax = self.a & self.x;
+        self.status.carry = operand_ax >= operand;
+        self.x = operand_ax.wrapping_sub(operand);
+        self.status.zero = self.x == 0;
+        self.status.negative = (self.x & 0x80) >> 7 == 1;
+    }
+
+    pub(in crate::cpu) fn las(&mut self, operand: u8) {
+        self.sp &= operand;
+        self.a = self.sp;
+        self.x = self.sp;
+        self.status.zero = self.a == 0;
+        self.status.negative = self.is_negative(self.a);
+    }
+
+    pub(in crate::cpu) fn lax(&mut self, operand: u8) {
+        self.lda(operand);
+        self.ldx(operand);
+    }
+
+    pub(in crate::cpu) fn nop_unoff(&mut self, _operand: u8) {}
+
+    pub(in crate::cpu) fn rla(&mut self, operand: u8) -> u8 {
+        let operand = self.rol(operand);
+        self.and(operand);
+        operand
+    }
+
+    pub(in crate::cpu) fn rra(&mut self, operand: u8) -> u8 {
+        let operand = self.ror(operand);
+        self.adc(operand);
+        operand
     }
 
-    pub(in crate::cpu) fn isc(&mut self, address: u16) {
-        let operand = self.bus.read(address).wrapping_add(1);
-        self.bus.write(address, operand);
-        self.sbc(address);
+    pub(in crate::cpu) fn sax(&mut self) -> u8 {
+        self.a & self.x
     }
 
-    pub(in crate::cpu) fn lax(&mut self, address: u16) {
-        self.lda(address);
-        self.pc -= 1;
-        self.ldx(address);
+    pub(in crate::cpu) fn shx(&mut self) -> u8 {
+        self.unstable_store(self.x)
     }
 
-    pub(in crate::cpu) fn nop_unoff(&mut self, _: u16) {
-        self.pc += 1;
+    pub(in crate::cpu) fn shy(&mut self) -> u8 {
+        self.unstable_store(self.y)
     }
 
-    pub(in crate::cpu) fn rla(&mut self, address: u16) {
-        self.rol_mem(address);
-        self.pc -= 1;
-        self.and(address);
+    pub(in crate::cpu) fn slo(&mut self, operand: u8) -> u8 {
+        let operand = self.asl(operand);
+        self.ora(operand);
+        operand
     }
 
-    pub(in crate::cpu) fn rra(&mut self, address: u16) {
-        self.ror_mem(address);
-        self.pc -= 1;
-        self.adc(address);
+    pub(in crate::cpu) fn sre(&mut self, operand: u8) -> u8 {
+        let operand = self.lsr(operand);
+        self.eor(operand);
+        operand
     }
 
-    pub(in crate::cpu) fn sax(&mut self, address: u16) {
-        self.bus.write(address, self.a & self.x);
-        self.pc += 1;
+    pub(in crate::cpu) fn tas(&mut self) -> u8 {
+        self.sp = self.a & self.x;
+        self.unstable_store(self.sp)
     }
 
-    pub(in crate::cpu) fn slo(&mut self, address: u16) {
-        self.asl_mem(address);
-        self.pc -= 1;
-        self.ora(address);
+    // the constant ORed into A varies between chips, $EE is the common one
+    pub(in crate::cpu) fn xaa(&mut self, operand: u8) {
+        self.lda((self.a | 0xEE) & self.x & operand);
     }
 
-    pub(in crate::cpu) fn sre(&mut self, address: u16) {
-        self.lsr_mem(address);
-        self.pc -= 1;
-        self.eor(address);
+    // SHX, SHY, TAS and AHX store the value ANDed with the high byte of the
+    // base address plus one, if indexing crossed a page that value also
+    // replaces the high byte of the address written to
+    fn unstable_store(&mut self, value: u8) -> u8 {
+        let hi = (self.address >> 8) as u8;
+        let value = value & hi.wrapping_sub(self.page_crossed as u8).wrapping_add(1);
+        if self.page_crossed {
+            self.address = ((value as u16) << 8) | (self.address & 0x00FF);
+        }
+        value
     }
 }
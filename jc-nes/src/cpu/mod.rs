@@ -1,12 +1,72 @@
 mod addressing;
 mod instructions;
 mod status;
+mod trace;
 
 use crate::bus::{Bus, Device};
 use crate::cpu::status::Status;
+use crate::state::{StateError, StateReader, StateWriter};
 
 const STACK_BASE: u16 = 0x0100;
 
+// How an instruction finds its operand
+#[derive(Clone, Copy, Default)]
+enum Mode {
+    #[default]
+    Imp,
+    Imm,
+    Zp,
+    Zpx,
+    Zpy,
+    Abs,
+    Absx,
+    Absy,
+    Ind,
+    Indx,
+    Indy,
+    Rel,
+}
+
+// What an instruction does with its operand, the ones that manage the stack
+// have a cycle sequence of their own
+#[derive(Clone, Copy, Default)]
+enum Operation {
+    Read(fn(&mut Cpu, u8)),
+    Write(fn(&mut Cpu) -> u8),
+    Modify(fn(&mut Cpu, u8) -> u8),
+    Implied(fn(&mut Cpu)),
+    Branch(fn(&Cpu) -> bool),
+    Push(fn(&mut Cpu) -> u8),
+    Pull(fn(&mut Cpu, u8)),
+    Jmp,
+    #[default]
+    Brk,
+    Jsr,
+    Rti,
+    Rts,
+    Jam,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Instruction {
+    mode: Mode,
+    operation: Operation,
+}
+
+/// Snapshot of the CPU registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuState {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    /// Status flags (NV-BDIZC)
+    pub p: u8,
+    pub sp: u8,
+    /// CPU cycles since power on
+    pub cycles: usize,
+}
+
 #[derive(Default)]
 pub struct Cpu {
     /// CPU registers
@@ -18,8 +78,23 @@ pub struct Cpu {
     status: Status,
 
     /// Implementation specific
-    cycle: u8,
-    extra_cycles: bool,
+    opcode: u8,
+    instruction: Instruction,
+    // cycles of the current instruction done so far, 0 between instructions
+    step: u8,
+    // latched between cycles while finding the operand
+    address: u16,
+    pointer: u8,
+    data: u8,
+    page_crossed: bool,
+    irq_line: bool,
+    nmi_pending: bool,
+    // polled before the last cycle of every instruction
+    interrupt_pending: bool,
+    // the BRK sequence is running for an IRQ, NMI or reset
+    hardware_interrupt: bool,
+    resetting: bool,
+    jammed: bool,
     pub(crate) bus: Bus,
 }
 
@@ -31,315 +106,437 @@ impl Cpu {
         }
     }
 
+    // runs one cycle, which is exactly one read or write on the bus
     pub fn clock(&mut self) {
-        if self.cycle == 0 {
-            let opcode = self.bus.read(self.pc);
-            self.process_opcode(opcode);
+        if self.jammed {
+            return;
+        }
+
+        if self.step == 0 {
+            self.fetch_opcode();
+        } else {
+            self.execute();
+        }
+
+        // interrupts are polled at the end of the second to last cycle, so
+        // they wait for the next instruction boundary (taken branches don't
+        // poll on their operand cycle)
+        // https://www.nesdev.org/wiki/CPU_interrupts
+        let taken_branch =
+            matches!(self.instruction.operation, Operation::Branch(_)) && self.step == 2;
+        if self.step != 0 && !taken_branch {
+            self.interrupt_pending = self.nmi_pending || (self.irq_line && !self.status.interrupt);
         }
-        self.cycle -= 1;
     }
 
+    // runs the interrupt sequence with its stack writes turned into reads
     pub fn reset(&mut self) {
-        let pcl = self.bus.read(0xFFFC);
-        let pch = self.bus.read(0xFFFD);
-        self.pc = ((pch as u16) << 8) | pcl as u16;
-
         self.a = 0;
         self.x = 0;
         self.y = 0;
-        self.sp = 0xFD;
-        self.status = Status::from(0x00);
+        self.sp = 0x00;
+        self.status = Status::from(0x24);
 
-        self.cycle = 8;
+        // takes over the next opcode fetch like IRQ and NMI do
+        self.step = 0;
+        self.nmi_pending = false;
+        self.interrupt_pending = true;
+        self.resetting = true;
+        self.jammed = false;
     }
 
+    // NMI is edge triggered, so it is latched until serviced
     pub fn nmi(&mut self) {
-        let pch = (self.pc >> 8) & 0xFF;
-        let pcl = self.pc & 0x00FF;
+        self.nmi_pending = true;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.a);
+        state.write_u8(self.x);
+        state.write_u8(self.y);
+        state.write_u16(self.pc);
+        state.write_u8(self.sp);
+        state.write_u8(u8::from(self.status));
+        state.write_u8(self.opcode);
+        state.write_u8(self.step);
+        state.write_u16(self.address);
+        state.write_u8(self.pointer);
+        state.write_u8(self.data);
+        state.write_bool(self.page_crossed);
+        state.write_bool(self.irq_line);
+        state.write_bool(self.nmi_pending);
+        state.write_bool(self.interrupt_pending);
+        state.write_bool(self.hardware_interrupt);
+        state.write_bool(self.resetting);
+        state.write_bool(self.jammed);
+        self.bus.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.a = state.read_u8()?;
+        self.x = state.read_u8()?;
+        self.y = state.read_u8()?;
+        self.pc = state.read_u16()?;
+        self.sp = state.read_u8()?;
+        self.status = Status::from(state.read_u8()?);
+        self.opcode = state.read_u8()?;
+        self.instruction = Cpu::decode(self.opcode);
+        self.step = state.read_u8()?;
+        self.address = state.read_u16()?;
+        self.pointer = state.read_u8()?;
+        self.data = state.read_u8()?;
+        self.page_crossed = state.read_bool()?;
+        self.irq_line = state.read_bool()?;
+        self.nmi_pending = state.read_bool()?;
+        self.interrupt_pending = state.read_bool()?;
+        self.hardware_interrupt = state.read_bool()?;
+        self.resetting = state.read_bool()?;
+        self.jammed = state.read_bool()?;
+        self.bus.load_state(state)
+    }
 
-        self.push_stack(pch as u8);
-        self.push_stack(pcl as u8);
+    /// True between instructions, when the next clock fetches an opcode
+    pub fn idle(&self) -> bool {
+        self.step == 0
+    }
 
-        self.status.b1 = false;
-        self.status.b2 = true;
-        self.status.interrupt = true;
+    pub fn state(&self, cycles: usize) -> CpuState {
+        CpuState {
+            pc: self.pc,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            p: u8::from(self.status),
+            sp: self.sp,
+            cycles,
+        }
+    }
+
+    /// True once a KIL/JAM opcode has halted the CPU, only a reset recovers
+    pub fn jammed(&self) -> bool {
+        self.jammed
+    }
 
-        self.push_stack(u8::from(self.status));
+    /// Level of the maskable interrupt line, serviced between instructions
+    /// while asserted and the interrupt disable flag is clear
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
 
-        let pcl = self.bus.read(0xFFFA);
-        let pch = self.bus.read(0xFFFB);
-        self.pc = ((pch as u16) << 8) | pcl as u16;
+    fn fetch_opcode(&mut self) {
+        if self.interrupt_pending {
+            // the opcode read is discarded and BRK runs in its place
+            self.bus.read(self.pc);
+            self.opcode = 0x00;
+            self.interrupt_pending = false;
+            self.hardware_interrupt = true;
+        } else {
+            self.opcode = self.fetch();
+        }
+        self.instruction = Cpu::decode(self.opcode);
+        self.step = 1;
+    }
 
-        self.cycle = 8;
+    fn execute(&mut self) {
+        match self.instruction.operation {
+            Operation::Brk => self.brk(),
+            Operation::Jsr => self.jsr(),
+            Operation::Rti => self.rti(),
+            Operation::Rts => self.rts(),
+            Operation::Push(operation) => self.push(operation),
+            Operation::Pull(operation) => self.pull(operation),
+            Operation::Jam => self.jam(),
+            _ => match self.instruction.mode {
+                Mode::Imp => self.imp(),
+                Mode::Imm => self.imm(),
+                Mode::Zp => self.zp(),
+                Mode::Zpx => self.zpx(),
+                Mode::Zpy => self.zpy(),
+                Mode::Abs => self.abs(),
+                Mode::Absx => self.absx(),
+                Mode::Absy => self.absy(),
+                Mode::Ind => self.ind(),
+                Mode::Indx => self.indx(),
+                Mode::Indy => self.indy(),
+                Mode::Rel => self.relative(),
+            },
+        }
     }
 }
 
-/// Opcode processing and execution and utility functions
+/// Opcode decoding and utility functions
 impl Cpu {
-    fn process_opcode(&mut self, opcode: u8) {
-        match opcode {
+    fn decode(opcode: u8) -> Instruction {
+        use Mode::*;
+        use Operation::*;
+
+        let (mode, operation) = match opcode {
             // Official Opcodes
-            0x00 => self.execute(Cpu::imp, Cpu::brk, 7, false),
-            0x01 => self.execute(Cpu::indx, Cpu::ora, 6, false),
-            0x05 => self.execute(Cpu::zp, Cpu::ora, 3, false),
-            0x06 => self.execute(Cpu::zp, Cpu::asl_mem, 5, false),
-            0x08 => self.execute(Cpu::imp, Cpu::php, 3, false),
-            0x09 => self.execute(Cpu::imm, Cpu::ora, 2, false),
-            0x0A => self.execute(Cpu::acc, Cpu::asl_acc, 2, false),
-            0x0D => self.execute(Cpu::abs, Cpu::ora, 4, false),
-            0x0E => self.execute(Cpu::abs, Cpu::asl_mem, 6, false),
-            0x10 => self.execute(Cpu::relative, Cpu::bpl, 2, true),
-            0x11 => self.execute(Cpu::indy, Cpu::ora, 5, false),
-            0x15 => self.execute(Cpu::zpx, Cpu::ora, 4, false),
-            0x16 => self.execute(Cpu::zpx, Cpu::asl_mem, 6, false),
-            0x18 => self.execute(Cpu::imp, Cpu::clc, 2, false),
-            0x19 => self.execute(Cpu::absy, Cpu::ora, 4, false),
-            0x1D => self.execute(Cpu::absx, Cpu::ora, 4, false),
-            0x1E => self.execute(Cpu::absx, Cpu::asl_mem, 7, false),
-            0x20 => self.execute(Cpu::abs, Cpu::jsr, 6, false),
-            0x21 => self.execute(Cpu::indx, Cpu::and, 6, false),
-            0x24 => self.execute(Cpu::zp, Cpu::bit, 3, false),
-            0x25 => self.execute(Cpu::zp, Cpu::and, 3, false),
-            0x26 => self.execute(Cpu::zp, Cpu::rol_mem, 5, false),
-            0x28 => self.execute(Cpu::imp, Cpu::plp, 4, false),
-            0x29 => self.execute(Cpu::imm, Cpu::and, 2, false),
-            0x2A => self.execute(Cpu::acc, Cpu::rol_acc, 2, false),
-            0x2C => self.execute(Cpu::abs, Cpu::bit, 4, false),
-            0x2D => self.execute(Cpu::abs, Cpu::and, 4, false),
-            0x2E => self.execute(Cpu::abs, Cpu::rol_mem, 6, false),
-            0x30 => self.execute(Cpu::relative, Cpu::bmi, 2, true),
-            0x31 => self.execute(Cpu::indy, Cpu::and, 5, false),
-            0x35 => self.execute(Cpu::zpx, Cpu::and, 4, false),
-            0x36 => self.execute(Cpu::zpx, Cpu::rol_mem, 6, false),
-            0x38 => self.execute(Cpu::imp, Cpu::sec, 2, false),
-            0x39 => self.execute(Cpu::absy, Cpu::and, 4, false),
-            0x3D => self.execute(Cpu::absx, Cpu::and, 4, false),
-            0x3E => self.execute(Cpu::absx, Cpu::rol_mem, 7, false),
-            0x40 => self.execute(Cpu::imp, Cpu::rti, 6, false),
-            0x41 => self.execute(Cpu::indx, Cpu::eor, 6, false),
-            0x45 => self.execute(Cpu::zp, Cpu::eor, 3, false),
-            0x46 => self.execute(Cpu::zp, Cpu::lsr_mem, 5, false),
-            0x48 => self.execute(Cpu::imp, Cpu::pha, 3, false),
-            0x49 => self.execute(Cpu::imm, Cpu::eor, 2, false),
-            0x4A => self.execute(Cpu::acc, Cpu::lsr_acc, 2, false),
-            0x4C => self.execute(Cpu::abs, Cpu::jmp, 3, false),
-            0x4D => self.execute(Cpu::abs, Cpu::eor, 4, false),
-            0x4E => self.execute(Cpu::abs, Cpu::lsr_mem, 6, false),
-            0x50 => self.execute(Cpu::relative, Cpu::bvc, 2, true),
-            0x51 => self.execute(Cpu::indy, Cpu::eor, 5, false),
-            0x55 => self.execute(Cpu::zpx, Cpu::eor, 4, false),
-            0x56 => self.execute(Cpu::zpx, Cpu::lsr_mem, 6, false),
-            0x58 => self.execute(Cpu::imp, Cpu::cli, 2, false),
-            0x59 => self.execute(Cpu::absy, Cpu::eor, 4, false),
-            0x5D => self.execute(Cpu::absx, Cpu::eor, 4, false),
-            0x5E => self.execute(Cpu::absx, Cpu::lsr_mem, 7, false),
-            0x60 => self.execute(Cpu::imp, Cpu::rts, 6, false),
-            0x61 => self.execute(Cpu::indx, Cpu::adc, 6, false),
-            0x65 => self.execute(Cpu::zp, Cpu::adc, 3, false),
-            0x66 => self.execute(Cpu::zp, Cpu::ror_mem, 5, false),
-            0x68 => self.execute(Cpu::imp, Cpu::pla, 4, false),
-            0x69 => self.execute(Cpu::imm, Cpu::adc, 2, false),
-            0x6A => self.execute(Cpu::acc, Cpu::ror_acc, 2, false),
-            0x6C => self.execute(Cpu::ind, Cpu::jmp, 5, false),
-            0x6D => self.execute(Cpu::abs, Cpu::adc, 4, false),
-            0x6E => self.execute(Cpu::abs, Cpu::ror_mem, 6, false),
-            0x70 => self.execute(Cpu::relative, Cpu::bvs, 2, true),
-            0x71 => self.execute(Cpu::indy, Cpu::adc, 5, true),
-            0x75 => self.execute(Cpu::zpx, Cpu::adc, 4, false),
-            0x76 => self.execute(Cpu::zpx, Cpu::ror_mem, 6, false),
-            0x78 => self.execute(Cpu::imp, Cpu::sei, 2, false),
-            0x79 => self.execute(Cpu::absy, Cpu::adc, 4, true),
-            0x7D => self.execute(Cpu::absx, Cpu::adc, 4, true),
-            0x7E => self.execute(Cpu::absx, Cpu::ror_mem, 7, false),
-            0x81 => self.execute(Cpu::indx, Cpu::sta, 6, false),
-            0x84 => self.execute(Cpu::zp, Cpu::sty, 3, false),
-            0x85 => self.execute(Cpu::zp, Cpu::sta, 3, false),
-            0x86 => self.execute(Cpu::zp, Cpu::stx, 3, false),
-            0x88 => self.execute(Cpu::imp, Cpu::dey, 2, false),
-            0x8A => self.execute(Cpu::imp, Cpu::txa, 2, false),
-            0x8C => self.execute(Cpu::abs, Cpu::sty, 4, false),
-            0x8D => self.execute(Cpu::abs, Cpu::sta, 4, false),
-            0x8E => self.execute(Cpu::abs, Cpu::stx, 4, false),
-            0x90 => self.execute(Cpu::relative, Cpu::bcc, 2, true),
-            0x91 => self.execute(Cpu::indy, Cpu::sta, 6, false),
-            0x94 => self.execute(Cpu::zpx, Cpu::sty, 4, false),
-            0x95 => self.execute(Cpu::zpx, Cpu::sta, 4, false),
-            0x96 => self.execute(Cpu::zpy, Cpu::stx, 4, false),
-            0x98 => self.execute(Cpu::imp, Cpu::tya, 2, false),
-            0x99 => self.execute(Cpu::absy, Cpu::sta, 5, false),
-            0x9A => self.execute(Cpu::imp, Cpu::txs, 2, false),
-            0x9D => self.execute(Cpu::absx, Cpu::sta, 5, false),
-            0xA0 => self.execute(Cpu::imm, Cpu::ldy, 2, false),
-            0xA1 => self.execute(Cpu::indx, Cpu::lda, 6, false),
-            0xA2 => self.execute(Cpu::imm, Cpu::ldx, 2, false),
-            0xA4 => self.execute(Cpu::zp, Cpu::ldy, 3, false),
-            0xA5 => self.execute(Cpu::zp, Cpu::lda, 3, false),
-            0xA6 => self.execute(Cpu::zp, Cpu::ldx, 3, false),
-            0xA8 => self.execute(Cpu::imp, Cpu::tay, 2, false),
-            0xA9 => self.execute(Cpu::imm, Cpu::lda, 2, false),
-            0xAA => self.execute(Cpu::imp, Cpu::tax, 2, false),
-            0xAC => self.execute(Cpu::abs, Cpu::ldy, 4, false),
-            0xAD => self.execute(Cpu::abs, Cpu::lda, 4, false),
-            0xAE => self.execute(Cpu::abs, Cpu::ldx, 4, false),
-            0xB0 => self.execute(Cpu::relative, Cpu::bcs, 2, true),
-            0xB1 => self.execute(Cpu::indy, Cpu::lda, 5, true),
-            0xB4 => self.execute(Cpu::zpx, Cpu::ldy, 4, false),
-            0xB5 => self.execute(Cpu::zpx, Cpu::lda, 4, false),
-            0xB6 => self.execute(Cpu::zpy, Cpu::ldx, 4, false),
-            0xB8 => self.execute(Cpu::imp, Cpu::clv, 2, false),
-            0xB9 => self.execute(Cpu::absy, Cpu::lda, 4, true),
-            0xBA => self.execute(Cpu::imp, Cpu::tsx, 2, false),
-            0xBC => self.execute(Cpu::absx, Cpu::ldy, 4, true),
-            0xBD => self.execute(Cpu::absx, Cpu::lda, 4, true),
-            0xBE => self.execute(Cpu::absy, Cpu::ldx, 4, true),
-            0xC0 => self.execute(Cpu::imm, Cpu::cpy, 2, false),
-            0xC1 => self.execute(Cpu::indx, Cpu::cmp, 6, false),
-            0xC4 => self.execute(Cpu::zp, Cpu::cpy, 3, false),
-            0xC5 => self.execute(Cpu::zp, Cpu::cmp, 3, false),
-            0xC6 => self.execute(Cpu::zp, Cpu::dec, 5, false),
-            0xC8 => self.execute(Cpu::imp, Cpu::iny, 2, false),
-            0xC9 => self.execute(Cpu::imm, Cpu::cmp, 2, false),
-            0xCA => self.execute(Cpu::imp, Cpu::dex, 2, false),
-            0xCC => self.execute(Cpu::abs, Cpu::cpy, 4, false),
-            0xCD => self.execute(Cpu::abs, Cpu::cmp, 4, false),
-            0xCE => self.execute(Cpu::abs, Cpu::dec, 6, false),
-            0xD0 => self.execute(Cpu::relative, Cpu::bne, 2, true),
-            0xD1 => self.execute(Cpu::indy, Cpu::cmp, 5, false),
-            0xD5 => self.execute(Cpu::zpx, Cpu::cmp, 4, false),
-            0xD6 => self.execute(Cpu::zpx, Cpu::dec, 6, false),
-            0xD8 => self.execute(Cpu::imp, Cpu::cld, 2, false),
-            0xD9 => self.execute(Cpu::absy, Cpu::cmp, 4, false),
-            0xDD => self.execute(Cpu::absx, Cpu::cmp, 4, false),
-            0xDE => self.execute(Cpu::absx, Cpu::dec, 7, false),
-            0xE0 => self.execute(Cpu::imm, Cpu::cpx, 2, false),
-            0xE1 => self.execute(Cpu::indx, Cpu::sbc, 6, false),
-            0xE4 => self.execute(Cpu::zp, Cpu::cpx, 3, false),
-            0xE5 => self.execute(Cpu::zp, Cpu::sbc, 3, false),
-            0xE6 => self.execute(Cpu::zp, Cpu::inc, 5, false),
-            0xE8 => self.execute(Cpu::imp, Cpu::inx, 2, false),
-            0xE9 => self.execute(Cpu::imm, Cpu::sbc, 2, false),
-            0xEA => self.execute(Cpu::imp, Cpu::nop, 2, false),
-            0xEC => self.execute(Cpu::abs, Cpu::cpx, 4, false),
-            0xED => self.execute(Cpu::abs, Cpu::sbc, 4, false),
-            0xEE => self.execute(Cpu::abs, Cpu::inc, 6, false),
-            0xF0 => self.execute(Cpu::relative, Cpu::beq, 2, true),
-            0xF1 => self.execute(Cpu::indy, Cpu::sbc, 5, false),
-            0xF5 => self.execute(Cpu::zpx, Cpu::sbc, 4, false),
-            0xF6 => self.execute(Cpu::zpx, Cpu::inc, 6, false),
-            0xF8 => self.execute(Cpu::imp, Cpu::sed, 2, false),
-            0xF9 => self.execute(Cpu::absy, Cpu::sbc, 4, false),
-            0xFD => self.execute(Cpu::absx, Cpu::sbc, 4, false),
-            0xFE => self.execute(Cpu::absx, Cpu::inc, 7, false),
+            0x00 => (Imp, Brk),
+            0x01 => (Indx, Read(Cpu::ora)),
+            0x05 => (Zp, Read(Cpu::ora)),
+            0x06 => (Zp, Modify(Cpu::asl)),
+            0x08 => (Imp, Push(Cpu::php)),
+            0x09 => (Imm, Read(Cpu::ora)),
+            0x0A => (Imp, Implied(Cpu::asl_acc)),
+            0x0D => (Abs, Read(Cpu::ora)),
+            0x0E => (Abs, Modify(Cpu::asl)),
+            0x10 => (Rel, Branch(Cpu::bpl)),
+            0x11 => (Indy, Read(Cpu::ora)),
+            0x15 => (Zpx, Read(Cpu::ora)),
+            0x16 => (Zpx, Modify(Cpu::asl)),
+            0x18 => (Imp, Implied(Cpu::clc)),
+            0x19 => (Absy, Read(Cpu::ora)),
+            0x1D => (Absx, Read(Cpu::ora)),
+            0x1E => (Absx, Modify(Cpu::asl)),
+            0x20 => (Abs, Jsr),
+            0x21 => (Indx, Read(Cpu::and)),
+            0x24 => (Zp, Read(Cpu::bit)),
+            0x25 => (Zp, Read(Cpu::and)),
+            0x26 => (Zp, Modify(Cpu::rol)),
+            0x28 => (Imp, Pull(Cpu::plp)),
+            0x29 => (Imm, Read(Cpu::and)),
+            0x2A => (Imp, Implied(Cpu::rol_acc)),
+            0x2C => (Abs, Read(Cpu::bit)),
+            0x2D => (Abs, Read(Cpu::and)),
+            0x2E => (Abs, Modify(Cpu::rol)),
+            0x30 => (Rel, Branch(Cpu::bmi)),
+            0x31 => (Indy, Read(Cpu::and)),
+            0x35 => (Zpx, Read(Cpu::and)),
+            0x36 => (Zpx, Modify(Cpu::rol)),
+            0x38 => (Imp, Implied(Cpu::sec)),
+            0x39 => (Absy, Read(Cpu::and)),
+            0x3D => (Absx, Read(Cpu::and)),
+            0x3E => (Absx, Modify(Cpu::rol)),
+            0x40 => (Imp, Rti),
+            0x41 => (Indx, Read(Cpu::eor)),
+            0x45 => (Zp, Read(Cpu::eor)),
+            0x46 => (Zp, Modify(Cpu::lsr)),
+            0x48 => (Imp, Push(Cpu::pha)),
+            0x49 => (Imm, Read(Cpu::eor)),
+            0x4A => (Imp, Implied(Cpu::lsr_acc)),
+            0x4C => (Abs, Jmp),
+            0x4D => (Abs, Read(Cpu::eor)),
+            0x4E => (Abs, Modify(Cpu::lsr)),
+            0x50 => (Rel, Branch(Cpu::bvc)),
+            0x51 => (Indy, Read(Cpu::eor)),
+            0x55 => (Zpx, Read(Cpu::eor)),
+            0x56 => (Zpx, Modify(Cpu::lsr)),
+            0x58 => (Imp, Implied(Cpu::cli)),
+            0x59 => (Absy, Read(Cpu::eor)),
+            0x5D => (Absx, Read(Cpu::eor)),
+            0x5E => (Absx, Modify(Cpu::lsr)),
+            0x60 => (Imp, Rts),
+            0x61 => (Indx, Read(Cpu::adc)),
+            0x65 => (Zp, Read(Cpu::adc)),
+            0x66 => (Zp, Modify(Cpu::ror)),
+            0x68 => (Imp, Pull(Cpu::pla)),
+            0x69 => (Imm, Read(Cpu::adc)),
+            0x6A => (Imp, Implied(Cpu::ror_acc)),
+            0x6C => (Ind, Jmp),
+            0x6D => (Abs, Read(Cpu::adc)),
+            0x6E => (Abs, Modify(Cpu::ror)),
+            0x70 => (Rel, Branch(Cpu::bvs)),
+            0x71 => (Indy, Read(Cpu::adc)),
+            0x75 => (Zpx, Read(Cpu::adc)),
+            0x76 => (Zpx, Modify(Cpu::ror)),
+            0x78 => (Imp, Implied(Cpu::sei)),
+            0x79 => (Absy, Read(Cpu::adc)),
+            0x7D => (Absx, Read(Cpu::adc)),
+            0x7E => (Absx, Modify(Cpu::ror)),
+            0x81 => (Indx, Write(Cpu::sta)),
+            0x84 => (Zp, Write(Cpu::sty)),
+            0x85 => (Zp, Write(Cpu::sta)),
+            0x86 => (Zp, Write(Cpu::stx)),
+            0x88 => (Imp, Implied(Cpu::dey)),
+            0x8A => (Imp, Implied(Cpu::txa)),
+            0x8C => (Abs, Write(Cpu::sty)),
+            0x8D => (Abs, Write(Cpu::sta)),
+            0x8E => (Abs, Write(Cpu::stx)),
+            0x90 => (Rel, Branch(Cpu::bcc)),
+            0x91 => (Indy, Write(Cpu::sta)),
+            0x94 => (Zpx, Write(Cpu::sty)),
+            0x95 => (Zpx, Write(Cpu::sta)),
+            0x96 => (Zpy, Write(Cpu::stx)),
+            0x98 => (Imp, Implied(Cpu::tya)),
+            0x99 => (Absy, Write(Cpu::sta)),
+            0x9A => (Imp, Implied(Cpu::txs)),
+            0x9D => (Absx, Write(Cpu::sta)),
+            0xA0 => (Imm, Read(Cpu::ldy)),
+            0xA1 => (Indx, Read(Cpu::lda)),
+            0xA2 => (Imm, Read(Cpu::ldx)),
+            0xA4 => (Zp, Read(Cpu::ldy)),
+            0xA5 => (Zp, Read(Cpu::lda)),
+            0xA6 => (Zp, Read(Cpu::ldx)),
+            0xA8 => (Imp, Implied(Cpu::tay)),
+            0xA9 => (Imm, Read(Cpu::lda)),
+            0xAA => (Imp, Implied(Cpu::tax)),
+            0xAC => (Abs, Read(Cpu::ldy)),
+            0xAD => (Abs, Read(Cpu::lda)),
+            0xAE => (Abs, Read(Cpu::ldx)),
+            0xB0 => (Rel, Branch(Cpu::bcs)),
+            0xB1 => (Indy, Read(Cpu::lda)),
+            0xB4 => (Zpx, Read(Cpu::ldy)),
+            0xB5 => (Zpx, Read(Cpu::lda)),
+            0xB6 => (Zpy, Read(Cpu::ldx)),
+            0xB8 => (Imp, Implied(Cpu::clv)),
+            0xB9 => (Absy, Read(Cpu::lda)),
+            0xBA => (Imp, Implied(Cpu::tsx)),
+            0xBC => (Absx, Read(Cpu::ldy)),
+            0xBD => (Absx, Read(Cpu::lda)),
+            0xBE => (Absy, Read(Cpu::ldx)),
+            0xC0 => (Imm, Read(Cpu::cpy)),
+            0xC1 => (Indx, Read(Cpu::cmp)),
+            0xC4 => (Zp, Read(Cpu::cpy)),
+            0xC5 => (Zp, Read(Cpu::cmp)),
+            0xC6 => (Zp, Modify(Cpu::dec)),
+            0xC8 => (Imp, Implied(Cpu::iny)),
+            0xC9 => (Imm, Read(Cpu::cmp)),
+            0xCA => (Imp, Implied(Cpu::dex)),
+            0xCC => (Abs, Read(Cpu::cpy)),
+            0xCD => (Abs, Read(Cpu::cmp)),
+            0xCE => (Abs, Modify(Cpu::dec)),
+            0xD0 => (Rel, Branch(Cpu::bne)),
+            0xD1 => (Indy, Read(Cpu::cmp)),
+            0xD5 => (Zpx, Read(Cpu::cmp)),
+            0xD6 => (Zpx, Modify(Cpu::dec)),
+            0xD8 => (Imp, Implied(Cpu::cld)),
+            0xD9 => (Absy, Read(Cpu::cmp)),
+            0xDD => (Absx, Read(Cpu::cmp)),
+            0xDE => (Absx, Modify(Cpu::dec)),
+            0xE0 => (Imm, Read(Cpu::cpx)),
+            0xE1 => (Indx, Read(Cpu::sbc)),
+            0xE4 => (Zp, Read(Cpu::cpx)),
+            0xE5 => (Zp, Read(Cpu::sbc)),
+            0xE6 => (Zp, Modify(Cpu::inc)),
+            0xE8 => (Imp, Implied(Cpu::inx)),
+            0xE9 => (Imm, Read(Cpu::sbc)),
+            0xEA => (Imp, Implied(Cpu::nop)),
+            0xEC => (Abs, Read(Cpu::cpx)),
+            0xED => (Abs, Read(Cpu::sbc)),
+            0xEE => (Abs, Modify(Cpu::inc)),
+            0xF0 => (Rel, Branch(Cpu::beq)),
+            0xF1 => (Indy, Read(Cpu::sbc)),
+            0xF5 => (Zpx, Read(Cpu::sbc)),
+            0xF6 => (Zpx, Modify(Cpu::inc)),
+            0xF8 => (Imp, Implied(Cpu::sed)),
+            0xF9 => (Absy, Read(Cpu::sbc)),
+            0xFD => (Absx, Read(Cpu::sbc)),
+            0xFE => (Absx, Modify(Cpu::inc)),
 
             // Unofficial Opcodes (used by some ROMs)
-            0x03 => self.execute(Cpu::indx, Cpu::slo, 8, false),
-            0x07 => self.execute(Cpu::zp, Cpu::slo, 5, false),
-            0x0F => self.execute(Cpu::abs, Cpu::slo, 6, false),
-            0x13 => self.execute(Cpu::indy, Cpu::slo, 8, false),
-            0x17 => self.execute(Cpu::zpx, Cpu::slo, 6, false),
-            0x1B => self.execute(Cpu::absy, Cpu::slo, 7, false),
-            0x1F => self.execute(Cpu::absx, Cpu::slo, 7, false),
-            0x23 => self.execute(Cpu::indx, Cpu::rla, 8, false),
-            0x27 => self.execute(Cpu::zp, Cpu::rla, 5, false),
-            0x2F => self.execute(Cpu::abs, Cpu::rla, 6, false),
-            0x33 => self.execute(Cpu::indy, Cpu::rla, 8, false),
-            0x37 => self.execute(Cpu::zpx, Cpu::rla, 6, false),
-            0x3B => self.execute(Cpu::absy, Cpu::rla, 7, false),
-            0x3F => self.execute(Cpu::absx, Cpu::rla, 7, false),
-            0x43 => self.execute(Cpu::indx, Cpu::sre, 8, false),
-            0x47 => self.execute(Cpu::zp, Cpu::sre, 5, false),
-            0x4F => self.execute(Cpu::abs, Cpu::sre, 6, false),
-            0x53 => self.execute(Cpu::indy, Cpu::sre, 8, false),
-            0x57 => self.execute(Cpu::zpx, Cpu::sre, 6, false),
-            0x5B => self.execute(Cpu::absy, Cpu::sre, 7, false),
-            0x5F => self.execute(Cpu::absx, Cpu::sre, 7, false),
-            0x63 => self.execute(Cpu::indx, Cpu::rra, 8, false),
-            0x67 => self.execute(Cpu::zp, Cpu::rra, 5, false),
-            0x6F => self.execute(Cpu::abs, Cpu::rra, 6, false),
-            0x73 => self.execute(Cpu::indy, Cpu::rra, 8, false),
-            0x77 => self.execute(Cpu::zpx, Cpu::rra, 6, false),
-            0x7B => self.execute(Cpu::absy, Cpu::rra, 7, false),
-            0x7F => self.execute(Cpu::absx, Cpu::rra, 7, false),
-            0x83 => self.execute(Cpu::indx, Cpu::sax, 6, false),
-            0x87 => self.execute(Cpu::zp, Cpu::sax, 3, false),
-            0x8F => self.execute(Cpu::abs, Cpu::sax, 4, false),
-            0x97 => self.execute(Cpu::zpy, Cpu::sax, 4, false),
-            0xA3 => self.execute(Cpu::indx, Cpu::lax, 6, false),
-            0xA7 => self.execute(Cpu::zp, Cpu::lax, 3, false),
-            0xAB => self.execute(Cpu::imm, Cpu::lax, 2, false),
-            0xAF => self.execute(Cpu::abs, Cpu::lax, 4, false),
-            0xB3 => self.execute(Cpu::indy, Cpu::lax, 5, true),
-            0xB7 => self.execute(Cpu::zpy, Cpu::lax, 4, false),
-            0xBF => self.execute(Cpu::absy, Cpu::lax, 4, true),
-            0xC3 => self.execute(Cpu::indx, Cpu::dcp, 8, false),
-            0xC7 => self.execute(Cpu::zp, Cpu::dcp, 5, false),
-            0xCF => self.execute(Cpu::abs, Cpu::dcp, 6, false),
-            0xD3 => self.execute(Cpu::indy, Cpu::dcp, 8, false),
-            0xD7 => self.execute(Cpu::zpx, Cpu::dcp, 6, false),
-            0xDB => self.execute(Cpu::absy, Cpu::dcp, 7, false),
-            0xDF => self.execute(Cpu::absx, Cpu::dcp, 7, false),
-            0xE3 => self.execute(Cpu::indx, Cpu::isc, 8, false),
-            0xE7 => self.execute(Cpu::zp, Cpu::isc, 5, false),
-            0xEF => self.execute(Cpu::abs, Cpu::isc, 6, false),
-            0xEB => self.execute(Cpu::imm, Cpu::sbc, 2, false),
-            0xF3 => self.execute(Cpu::indy, Cpu::isc, 8, false),
-            0xF7 => self.execute(Cpu::zpx, Cpu::isc, 6, false),
-            0xFB => self.execute(Cpu::absy, Cpu::isc, 7, false),
-            0xFF => self.execute(Cpu::absx, Cpu::isc, 7, false),
+            0x03 => (Indx, Modify(Cpu::slo)),
+            0x07 => (Zp, Modify(Cpu::slo)),
+            0x0F => (Abs, Modify(Cpu::slo)),
+            0x13 => (Indy, Modify(Cpu::slo)),
+            0x17 => (Zpx, Modify(Cpu::slo)),
+            0x1B => (Absy, Modify(Cpu::slo)),
+            0x1F => (Absx, Modify(Cpu::slo)),
+            0x23 => (Indx, Modify(Cpu::rla)),
+            0x27 => (Zp, Modify(Cpu::rla)),
+            0x2F => (Abs, Modify(Cpu::rla)),
+            0x33 => (Indy, Modify(Cpu::rla)),
+            0x37 => (Zpx, Modify(Cpu::rla)),
+            0x3B => (Absy, Modify(Cpu::rla)),
+            0x3F => (Absx, Modify(Cpu::rla)),
+            0x43 => (Indx, Modify(Cpu::sre)),
+            0x47 => (Zp, Modify(Cpu::sre)),
+            0x4F => (Abs, Modify(Cpu::sre)),
+            0x53 => (Indy, Modify(Cpu::sre)),
+            0x57 => (Zpx, Modify(Cpu::sre)),
+            0x5B => (Absy, Modify(Cpu::sre)),
+            0x5F => (Absx, Modify(Cpu::sre)),
+            0x63 => (Indx, Modify(Cpu::rra)),
+            0x67 => (Zp, Modify(Cpu::rra)),
+            0x6F => (Abs, Modify(Cpu::rra)),
+            0x73 => (Indy, Modify(Cpu::rra)),
+            0x77 => (Zpx, Modify(Cpu::rra)),
+            0x7B => (Absy, Modify(Cpu::rra)),
+            0x7F => (Absx, Modify(Cpu::rra)),
+            0x83 => (Indx, Write(Cpu::sax)),
+            0x87 => (Zp, Write(Cpu::sax)),
+            0x8F => (Abs, Write(Cpu::sax)),
+            0x97 => (Zpy, Write(Cpu::sax)),
+            0xA3 => (Indx, Read(Cpu::lax)),
+            0xA7 => (Zp, Read(Cpu::lax)),
+            0xAB => (Imm, Read(Cpu::lax)),
+            0xAF => (Abs, Read(Cpu::lax)),
+            0xB3 => (Indy, Read(Cpu::lax)),
+            0xB7 => (Zpy, Read(Cpu::lax)),
+            0xBF => (Absy, Read(Cpu::lax)),
+            0xC3 => (Indx, Modify(Cpu::dcp)),
+            0xC7 => (Zp, Modify(Cpu::dcp)),
+            0xCF => (Abs, Modify(Cpu::dcp)),
+            0xD3 => (Indy, Modify(Cpu::dcp)),
+            0xD7 => (Zpx, Modify(Cpu::dcp)),
+            0xDB => (Absy, Modify(Cpu::dcp)),
+            0xDF => (Absx, Modify(Cpu::dcp)),
+            0xE3 => (Indx, Modify(Cpu::isc)),
+            0xE7 => (Zp, Modify(Cpu::isc)),
+            0xEF => (Abs, Modify(Cpu::isc)),
+            0xEB => (Imm, Read(Cpu::sbc)),
+            0xF3 => (Indy, Modify(Cpu::isc)),
+            0xF7 => (Zpx, Modify(Cpu::isc)),
+            0xFB => (Absy, Modify(Cpu::isc)),
+            0xFF => (Absx, Modify(Cpu::isc)),
 
             // Unofficial NOPs
-            0x0C => self.execute(Cpu::abs, Cpu::nop_unoff, 4, false),
-            0x04 | 0x44 | 0x64 => self.execute(Cpu::zp, Cpu::nop_unoff, 3, false),
-            0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => {
-                self.execute(Cpu::zpx, Cpu::nop_unoff, 4, false)
-            }
-            0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => self.execute(Cpu::imp, Cpu::nop, 2, false),
-            0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => {
-                self.execute(Cpu::absx, Cpu::nop_unoff, 4, true)
-            }
-            0x80 => self.execute(Cpu::imm, Cpu::nop_unoff, 2, false),
+            0x0C => (Abs, Read(Cpu::nop_unoff)),
+            0x04 | 0x44 | 0x64 => (Zp, Read(Cpu::nop_unoff)),
+            0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => (Zpx, Read(Cpu::nop_unoff)),
+            0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => (Imp, Implied(Cpu::nop)),
+            0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => (Absx, Read(Cpu::nop_unoff)),
+            0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 => (Imm, Read(Cpu::nop_unoff)),
 
-            // Unknown Opcode
-            _ => eprintln!("Unknown opcode 0x{:0X} at 0x{:0X}", opcode, self.pc),
+            // Unstable Opcodes
+            // https://www.nesdev.org/wiki/CPU_unofficial_opcodes
+            0x0B | 0x2B => (Imm, Read(Cpu::anc)),
+            0x4B => (Imm, Read(Cpu::alr)),
+            0x6B => (Imm, Read(Cpu::arr)),
+            0x8B => (Imm, Read(Cpu::xaa)),
+            0x93 => (Indy, Write(Cpu::ahx)),
+            0x9B => (Absy, Write(Cpu::tas)),
+            0x9C => (Absx, Write(Cpu::shy)),
+            0x9E => (Absy, Write(Cpu::shx)),
+            0x9F => (Absy, Write(Cpu::ahx)),
+            0xBB => (Absy, Read(Cpu::las)),
+            0xCB => (Imm, Read(Cpu::axs)),
+
+            // KIL/JAM halt the CPU until reset
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2 | 0xD2 | 0xF2 => {
+                (Imp, Jam)
+            }
         };
+        Instruction { mode, operation }
     }
 
-    fn execute<T>(
-        &mut self,
-        address_mode_fn: fn(&mut Cpu) -> T,
-        opcode_fn: fn(&mut Cpu, T),
-        cycles: u8,
-        extra_cycles: bool,
-    ) {
-        self.extra_cycles = extra_cycles;
-        let address = address_mode_fn(self);
-        opcode_fn(self, address);
-        self.cycle += cycles;
+    // reads the byte at PC and moves past it
+    fn fetch(&mut self) -> u8 {
+        let data = self.bus.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        data
     }
 
-    fn push_stack(&mut self, val: u8) {
-        self.bus.write(STACK_BASE + self.sp as u16, val);
-        self.sp -= 1;
+    fn next(&mut self) {
+        self.step += 1;
     }
 
-    fn pop_stack(&mut self) -> u8 {
-        self.sp += 1;
-        self.bus.read(STACK_BASE + self.sp as u16)
+    fn done(&mut self) {
+        self.step = 0;
     }
 
-    fn relative_jump(&mut self, jump: bool, operand: i8) {
-        if jump {
-            self.cycle += 1;
-            let next = (self.pc as i32 + operand as i32) as u16 + 1;
-            self.cycle += self.page_crossed(self.pc + 1, next) as u8;
-            self.pc = next;
+    // reset moves the stack pointer without writing
+    fn push_stack(&mut self, val: u8) {
+        if self.resetting {
+            self.peek_stack();
         } else {
-            self.pc += 1
+            self.bus.write(STACK_BASE + self.sp as u16, val);
         }
+        self.sp = self.sp.wrapping_sub(1);
     }
 
-    fn page_crossed(&self, addr1: u16, addr2: u16) -> bool {
-        (addr1 & 0xFF00) != (addr2 & 0xFF00)
+    fn peek_stack(&mut self) -> u8 {
+        self.bus.read(STACK_BASE + self.sp as u16)
     }
 
     fn is_negative(&self, operand: u8) -> bool {
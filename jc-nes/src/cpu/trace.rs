@@ -0,0 +1,171 @@
+use crate::{
+    bus::Device,
+    cpu::{Cpu, Mode, Operation},
+};
+
+// nestest.log names, unofficial opcodes are marked with a '*'
+#[rustfmt::skip]
+const MNEMONICS: [&str; 256] = [
+    "BRK", "ORA", "*JAM", "*SLO", "*NOP", "ORA", "ASL", "*SLO", "PHP", "ORA", "ASL", "*ANC", "*NOP", "ORA", "ASL", "*SLO",
+    "BPL", "ORA", "*JAM", "*SLO", "*NOP", "ORA", "ASL", "*SLO", "CLC", "ORA", "*NOP", "*SLO", "*NOP", "ORA", "ASL", "*SLO",
+    "JSR", "AND", "*JAM", "*RLA", "BIT", "AND", "ROL", "*RLA", "PLP", "AND", "ROL", "*ANC", "BIT", "AND", "ROL", "*RLA",
+    "BMI", "AND", "*JAM", "*RLA", "*NOP", "AND", "ROL", "*RLA", "SEC", "AND", "*NOP", "*RLA", "*NOP", "AND", "ROL", "*RLA",
+    "RTI", "EOR", "*JAM", "*SRE", "*NOP", "EOR", "LSR", "*SRE", "PHA", "EOR", "LSR", "*ALR", "JMP", "EOR", "LSR", "*SRE",
+    "BVC", "EOR", "*JAM", "*SRE", "*NOP", "EOR", "LSR", "*SRE", "CLI", "EOR", "*NOP", "*SRE", "*NOP", "EOR", "LSR", "*SRE",
+    "RTS", "ADC", "*JAM", "*RRA", "*NOP", "ADC", "ROR", "*RRA", "PLA", "ADC", "ROR", "*ARR", "JMP", "ADC", "ROR", "*RRA",
+    "BVS", "ADC", "*JAM", "*RRA", "*NOP", "ADC", "ROR", "*RRA", "SEI", "ADC", "*NOP", "*RRA", "*NOP", "ADC", "ROR", "*RRA",
+    "*NOP", "STA", "*NOP", "*SAX", "STY", "STA", "STX", "*SAX", "DEY", "*NOP", "TXA", "*XAA", "STY", "STA", "STX", "*SAX",
+    "BCC", "STA", "*JAM", "*AHX", "STY", "STA", "STX", "*SAX", "TYA", "STA", "TXS", "*TAS", "*SHY", "STA", "*SHX", "*AHX",
+    "LDY", "LDA", "LDX", "*LAX", "LDY", "LDA", "LDX", "*LAX", "TAY", "LDA", "TAX", "*LAX", "LDY", "LDA", "LDX", "*LAX",
+    "BCS", "LDA", "*JAM", "*LAX", "LDY", "LDA", "LDX", "*LAX", "CLV", "LDA", "TSX", "*LAS", "LDY", "LDA", "LDX", "*LAX",
+    "CPY", "CMP", "*NOP", "*DCP", "CPY", "CMP", "DEC", "*DCP", "INY", "CMP", "DEX", "*AXS", "CPY", "CMP", "DEC", "*DCP",
+    "BNE", "CMP", "*JAM", "*DCP", "*NOP", "CMP", "DEC", "*DCP", "CLD", "CMP", "*NOP", "*DCP", "*NOP", "CMP", "DEC", "*DCP",
+    "CPX", "SBC", "*NOP", "*ISB", "CPX", "SBC", "INC", "*ISB", "INX", "SBC", "NOP", "*SBC", "CPX", "SBC", "INC", "*ISB",
+    "BEQ", "SBC", "*JAM", "*ISB", "*NOP", "SBC", "INC", "*ISB", "SED", "SBC", "*NOP", "*ISB", "*NOP", "SBC", "INC", "*ISB",
+];
+
+/// Execution trace in the format of nestest.log
+/// (https://www.qmtpro.com/~nes/misc/nestest.log)
+impl Cpu {
+    // the instruction about to run along with the registers, e.g.
+    // C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD
+    // nothing while jammed or when an interrupt takes over the next fetch
+    pub(crate) fn trace(&mut self) -> Option<String> {
+        if self.jammed || self.interrupt_pending {
+            return None;
+        }
+
+        let opcode = self.peek(self.pc);
+        let instruction = Cpu::decode(opcode.unwrap_or(0x00));
+        let length = match instruction.mode {
+            Mode::Imp => 1,
+            Mode::Abs | Mode::Absx | Mode::Absy | Mode::Ind => 3,
+            _ => 2,
+        };
+        let bytes: Vec<Option<u8>> = (0..length)
+            .map(|i| self.peek(self.pc.wrapping_add(i)))
+            .collect();
+        let hex = bytes
+            .iter()
+            .map(|byte| byte.map_or("??".to_string(), |byte| format!("{:02X}", byte)))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mnemonic = match opcode {
+            Some(opcode) => MNEMONICS[opcode as usize],
+            None => "???",
+        };
+        let operand = match opcode {
+            Some(opcode) => self.disassemble(opcode, &bytes),
+            None => String::new(),
+        };
+        let disassembly = format!("{} {}", mnemonic.trim_start_matches('*'), operand);
+
+        Some(format!(
+            "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            self.pc,
+            hex,
+            if mnemonic.starts_with('*') { '*' } else { ' ' },
+            disassembly.trim_end(),
+            self.a,
+            self.x,
+            self.y,
+            u8::from(self.status),
+            self.sp,
+        ))
+    }
+
+    // the operand the way nestest.log shows it, with the effective address
+    // and the value found there
+    fn disassemble(&mut self, opcode: u8, bytes: &[Option<u8>]) -> String {
+        let instruction = Cpu::decode(opcode);
+        let lo = bytes.get(1).copied().flatten().unwrap_or(0x00);
+        let hi = bytes.get(2).copied().flatten().unwrap_or(0x00);
+        let absolute = u16::from_le_bytes([lo, hi]);
+
+        match instruction.mode {
+            Mode::Imp => match opcode {
+                0x0A | 0x2A | 0x4A | 0x6A => "A".to_string(),
+                _ => String::new(),
+            },
+            Mode::Imm => format!("#${:02X}", lo),
+            Mode::Zp => format!("${:02X}{}", lo, self.value(lo as u16)),
+            Mode::Zpx => {
+                let address = lo.wrapping_add(self.x);
+                let value = self.value(address as u16);
+                format!("${:02X},X @ {:02X}{}", lo, address, value)
+            }
+            Mode::Zpy => {
+                let address = lo.wrapping_add(self.y);
+                let value = self.value(address as u16);
+                format!("${:02X},Y @ {:02X}{}", lo, address, value)
+            }
+            Mode::Abs => match instruction.operation {
+                Operation::Jmp | Operation::Jsr => format!("${:04X}", absolute),
+                _ => format!("${:04X}{}", absolute, self.value(absolute)),
+            },
+            Mode::Absx => {
+                let address = absolute.wrapping_add(self.x as u16);
+                let value = self.value(address);
+                format!("${:04X},X @ {:04X}{}", absolute, address, value)
+            }
+            Mode::Absy => {
+                let address = absolute.wrapping_add(self.y as u16);
+                let value = self.value(address);
+                format!("${:04X},Y @ {:04X}{}", absolute, address, value)
+            }
+            Mode::Ind => {
+                // with the same page wrap as the JMP itself
+                let next = (absolute & 0xFF00) | (absolute.wrapping_add(1) & 0x00FF);
+                match (self.peek(absolute), self.peek(next)) {
+                    (Some(lo), Some(hi)) => {
+                        format!("(${:04X}) = {:04X}", absolute, u16::from_le_bytes([lo, hi]))
+                    }
+                    _ => format!("(${:04X})", absolute),
+                }
+            }
+            Mode::Indx => {
+                let pointer = lo.wrapping_add(self.x);
+                let address = self.zero_page_pointer(pointer);
+                let value = self.value(address);
+                format!(
+                    "(${:02X},X) @ {:02X} = {:04X}{}",
+                    lo, pointer, address, value
+                )
+            }
+            Mode::Indy => {
+                let base = self.zero_page_pointer(lo);
+                let address = base.wrapping_add(self.y as u16);
+                let value = self.value(address);
+                format!("(${:02X}),Y = {:04X} @ {:04X}{}", lo, base, address, value)
+            }
+            Mode::Rel => {
+                let target = self.pc.wrapping_add(2).wrapping_add(lo as i8 as u16);
+                format!("${:04X}", target)
+            }
+        }
+    }
+
+    // " = xx" for operands that can be peeked
+    fn value(&mut self, address: u16) -> String {
+        match self.peek(address) {
+            Some(value) => format!(" = {:02X}", value),
+            None => String::new(),
+        }
+    }
+
+    fn zero_page_pointer(&mut self, pointer: u8) -> u16 {
+        let lo = self.bus.read(pointer as u16);
+        let hi = self.bus.read(pointer.wrapping_add(1) as u16);
+        u16::from_le_bytes([lo, hi])
+    }
+
+    // reading PPU, APU and mapper registers has side effects, so only RAM and
+    // cartridge memory are looked at
+    fn peek(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x0000..=0x1FFF | 0x6000..=0xFFFF => Some(self.bus.read(address)),
+            _ => None,
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::bus::Device;
+use crate::state::{StateError, StateReader, StateWriter};
 
 #[cfg(feature = "web")]
 use wasm_bindgen::prelude::*;
@@ -31,6 +32,17 @@ impl Device for Gamepad {
     fn write(&mut self, _address: u16, _data: u8) {
         self.state_snapshot = self.state;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.state);
+        state.write_u8(self.state_snapshot);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.state = state.read_u8()?;
+        self.state_snapshot = state.read_u8()?;
+        Ok(())
+    }
 }
 
 impl Gamepad {
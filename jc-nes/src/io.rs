@@ -0,0 +1,54 @@
+use crate::apu::Apu;
+use crate::bus::{Device, SharedMut};
+use crate::gamepad::Gamepad;
+use crate::ppu::dma::OamDma;
+
+// https://wiki.nesdev.com/w/index.php/2A03
+pub struct Io {
+    apu: SharedMut<Apu>,
+    dma_controller: SharedMut<OamDma>,
+    gamepad1: SharedMut<Gamepad>,
+    gamepad2: SharedMut<Gamepad>,
+}
+
+impl Io {
+    pub fn new(
+        apu: SharedMut<Apu>,
+        dma_controller: SharedMut<OamDma>,
+        gamepad1: SharedMut<Gamepad>,
+        gamepad2: SharedMut<Gamepad>,
+    ) -> Io {
+        Io {
+            apu,
+            dma_controller,
+            gamepad1,
+            gamepad2,
+        }
+    }
+}
+
+// This interface is exposed for the APU and I/O registers ($4000-$401F on CPU Bus)
+impl Device for Io {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x14 => self.dma_controller.read(address),
+            0x16 => self.gamepad1.read(address),
+            0x17 => self.gamepad2.read(address),
+            0x00..=0x15 => self.apu.read(address),
+            _ => 0x00,
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x14 => self.dma_controller.write(address, data),
+            // strobe is shared by both controller ports
+            0x16 => {
+                self.gamepad1.write(address, data);
+                self.gamepad2.write(address, data);
+            }
+            0x00..=0x17 => self.apu.write(address, data),
+            _ => (),
+        }
+    }
+}
@@ -1,11 +1,21 @@
+mod apu;
 mod bus;
 mod cartridge;
 mod cpu;
 mod gamepad;
+mod io;
 mod nes;
 mod ppu;
 mod ram;
+mod state;
 
+pub use crate::bus::Fetch;
+pub use crate::cartridge::error::LoadError;
+pub use crate::cartridge::info::{CartridgeInfo, ConsoleType, RomFormat, Timing};
+pub use crate::cartridge::mappers::{Mapper, MapperConstructor};
+pub use crate::cartridge::{Cartridge, MirrorMode};
+pub use crate::cpu::CpuState;
 pub use crate::gamepad::Button;
 pub use crate::nes::Nes;
 pub use crate::ppu::{HEIGHT as SCREEN_HEIGHT, WIDTH as SCREEN_WIDTH};
+pub use crate::state::{StateError, StateReader, StateWriter};
@@ -1,13 +1,19 @@
+use crate::apu::{Apu, DEFAULT_SAMPLE_RATE};
 use crate::bus::{Bus, Device, SharedMut};
-use crate::cartridge::mappers;
+use crate::cartridge::error::LoadError;
+use crate::cartridge::info::CartridgeInfo;
+use crate::cartridge::mappers::{CpuPort, MapperConstructor, MapperRegistry, PpuPort, Slot};
 use crate::cartridge::Cartridge;
-use crate::cpu::Cpu;
+use crate::cpu::{Cpu, CpuState};
 use crate::gamepad::{Button, Gamepad};
+use crate::io::Io;
 use crate::ppu::dma::OamDma;
 use crate::ppu::palette::Palette;
 use crate::ppu::Ppu;
 use crate::ram::Ram;
+use crate::state::{self, StateError, StateReader, StateWriter};
 use std::cell::RefCell;
+use std::io::Write;
 use std::rc::Rc;
 
 #[cfg(feature = "web")]
@@ -17,10 +23,16 @@ use wasm_bindgen::prelude::*;
 pub struct Nes {
     cpu: Cpu,
     ppu: SharedMut<Ppu>,
+    apu: SharedMut<Apu>,
     dma_controller: SharedMut<OamDma>,
     gamepad1: SharedMut<Gamepad>,
     gamepad2: SharedMut<Gamepad>,
+    mapper: Slot,
+    mappers: MapperRegistry,
+    cartridge_info: Option<CartridgeInfo>,
+    rom_hash: u64,
     cycles: usize,
+    trace_sink: Option<Box<dyn Write>>,
 }
 
 #[cfg_attr(feature = "web", wasm_bindgen)]
@@ -29,17 +41,13 @@ impl Nes {
     pub fn new() -> Nes {
         // build PPU bus
         let mut ppu_bus = Bus::default();
-        let nametbl1 = Ram::new(vec![0u8; 1024]);
-        let nametbl2 = Ram::new(vec![0u8; 1024]);
-        let nametbl3 = Ram::new(vec![0u8; 1024]);
-        let nametbl4 = Ram::new(vec![0u8; 1024]);
         let palette = Palette::new();
 
+        // the cartridge slot, which serves CHR and nametables once a ROM is loaded
+        let mapper = Rc::new(RefCell::new(None));
+
         // connect (and mirror) devices to PPU bus
-        ppu_bus.connect(0x2000..=0x23FF, nametbl1);
-        ppu_bus.connect(0x2400..=0x27FF, nametbl2);
-        ppu_bus.connect(0x2800..=0x2BFF, nametbl3);
-        ppu_bus.connect(0x2C00..=0x2FFF, nametbl4);
+        ppu_bus.connect(0x0000..=0x2FFF, PpuPort(mapper.clone()));
         ppu_bus.connect(0x3F00..=0x3FFF, palette);
         ppu_bus.add_mirror(0x3000..=0x3EFF, 0x2EFF);
         ppu_bus.add_mirror(0x3F20..=0x3FFF, 0x3F1F);
@@ -53,19 +61,19 @@ impl Nes {
         let gamepad1 = Rc::new(RefCell::new(Gamepad::default()));
         let gamepad2 = Rc::new(RefCell::new(Gamepad::default()));
         let dma_controller = Rc::new(RefCell::new(OamDma::default()));
+        let apu = Rc::new(RefCell::new(Apu::new(DEFAULT_SAMPLE_RATE)));
+        let io = Io::new(
+            apu.clone(),
+            dma_controller.clone(),
+            gamepad1.clone(),
+            gamepad2.clone(),
+        );
 
         // connect (and mirror) devices to CPU bus
         cpu_bus.connect(0x0000..=0x1FFF, ram);
         cpu_bus.connect(0x2000..=0x3FFF, ppu.clone());
-        cpu_bus.connect(0x4014..=0x4014, dma_controller.clone());
-        cpu_bus.connect(0x4016..=0x4016, gamepad1.clone());
-        cpu_bus.connect(0x4017..=0x4017, gamepad2.clone());
-
-        // (APU address space and others)
-        cpu_bus.connect(0x4000..=0x4013, Ram::new(vec![0u8; 32]));
-        cpu_bus.connect(0x4015..=0x4015, Ram::new(vec![0u8; 32]));
-        cpu_bus.connect(0x4018..=0x401F, Ram::new(vec![0u8; 32]));
-        cpu_bus.connect(0x4020..=0x7FFF, Ram::new(vec![0u8; 15 * 1024]));
+        cpu_bus.connect(0x4000..=0x401F, io);
+        cpu_bus.connect(0x4020..=0xFFFF, CpuPort(mapper.clone()));
 
         // add mirrors
         cpu_bus.add_mirror(0x0000..=0x1FFF, 0x07FF);
@@ -76,27 +84,59 @@ impl Nes {
         Nes {
             cpu,
             ppu,
+            apu,
             dma_controller,
             gamepad1,
             gamepad2,
+            mapper,
+            mappers: MapperRegistry::default(),
+            cartridge_info: None,
+            rom_hash: 0,
             cycles: 0,
+            trace_sink: None,
         }
     }
 
-    pub fn load_rom(&mut self, rom: &[u8]) {
-        let cartridge = Cartridge::new(rom);
-        self.ppu.borrow_mut().mirror_mode = cartridge.mirror;
-        match cartridge.mapper_id {
-            0 => self.connect_mapper(mappers::mapper000::new_mapper(cartridge)),
-            3 => self.connect_mapper(mappers::mapper003::new_mapper(cartridge)),
-            id => panic!("Unimplemented mapper {}", id),
-        };
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), LoadError> {
+        let cartridge = Cartridge::new(rom)?;
+        let info = cartridge.info.clone();
+        let mapper = self.mappers.create(cartridge)?;
+        self.mapper.replace(Some(mapper));
+        self.cartridge_info = Some(info);
+        self.rom_hash = state::hash(rom);
+        Ok(())
     }
 
     pub fn clock(&mut self) {
+        if self.trace_sink.is_some() && self.instruction_boundary() {
+            self.trace();
+        }
+
         self.ppu.borrow_mut().clock();
 
-        if self.cycles % 3 == 0 {
+        if self.cycles.is_multiple_of(3) {
+            let (mapper_irq, expansion_output) = match self.mapper.borrow_mut().as_mut() {
+                Some(mapper) => {
+                    mapper.clock();
+                    (mapper.irq(), mapper.audio_output())
+                }
+                None => (false, 0.0),
+            };
+            {
+                let mut apu = self.apu.borrow_mut();
+                apu.set_expansion_output(expansion_output);
+                apu.clock();
+            }
+
+            let dmc_request = self.apu.borrow().dmc_request();
+            if let Some(address) = dmc_request {
+                let data = self.cpu.bus.read(address);
+                self.apu.borrow_mut().dmc_fill(data);
+            }
+
+            let irq = self.apu.borrow().irq() || mapper_irq;
+            self.cpu.set_irq(irq);
+
             if self.dma_controller.borrow().dma_in_progress {
                 self.dma_controller
                     .borrow_mut()
@@ -114,9 +154,89 @@ impl Nes {
         self.cycles += 1;
     }
 
+    /// Runs until the next frame is complete, which is then available from `get_frame`.
+    /// Returns the number of master clock cycles consumed.
+    pub fn run_frame(&mut self) -> usize {
+        let start = self.cycles;
+        self.ppu.borrow_mut().frame_complete = false;
+        while !self.ppu.borrow().frame_complete {
+            self.clock();
+        }
+        self.cycles - start
+    }
+
+    /// Runs until the CPU has executed one full instruction (including any
+    /// interrupt or DMA in the way). Returns the number of master clock cycles consumed.
+    pub fn step_instruction(&mut self) -> usize {
+        let start = self.cycles;
+        while !self.instruction_boundary() {
+            self.clock();
+        }
+        self.clock();
+        while !self.instruction_boundary() {
+            self.clock();
+        }
+        self.cycles - start
+    }
+
     pub fn reset(&mut self) {
         self.cpu.reset();
         self.ppu.borrow_mut().reset();
+        self.apu.borrow_mut().reset();
+    }
+
+    /// True once the CPU has run into a KIL/JAM opcode and stopped, the PPU
+    /// and APU keep running but nothing happens until `reset`
+    pub fn jammed(&self) -> bool {
+        self.cpu.jammed()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.apu.borrow_mut().set_sample_rate(sample_rate);
+    }
+
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.apu.borrow_mut().take_samples()
+    }
+
+    /// Contents of the battery-backed PRG-RAM (followed by any battery-backed
+    /// mapper RAM), if the cartridge has a battery
+    pub fn battery_ram(&self) -> Option<Vec<u8>> {
+        self.mapper.borrow().as_ref()?.battery_ram()
+    }
+
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        if let Some(mapper) = self.mapper.borrow_mut().as_mut() {
+            mapper.load_battery_ram(data);
+        }
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new(self.rom_hash);
+        self.cpu.save_state(&mut state);
+        self.ppu.borrow().save_state(&mut state);
+        self.apu.borrow().save_state(&mut state);
+        self.dma_controller.borrow().save_state(&mut state);
+        self.gamepad1.borrow().save_state(&mut state);
+        self.gamepad2.borrow().save_state(&mut state);
+        if let Some(mapper) = self.mapper.borrow().as_ref() {
+            mapper.save_state(&mut state);
+        }
+        state.write_usize(self.cycles);
+        state.finish()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data, self.rom_hash)?;
+
+        // a state can fail halfway through, so keep a copy to roll back to
+        let backup = self.save_state();
+        let result = self.restore(&mut state).and_then(|_| state.finish());
+        if result.is_err() {
+            let mut backup = StateReader::new(&backup, self.rom_hash)?;
+            self.restore(&mut backup)?;
+        }
+        result
     }
 
     #[cfg(not(feature = "web"))]
@@ -156,16 +276,90 @@ impl Nes {
             _ => panic!("expected either controller '1' or '2'"),
         }
     }
+}
+
+impl Nes {
+    // the next clock starts a new CPU instruction
+    fn instruction_boundary(&self) -> bool {
+        self.cycles.is_multiple_of(3)
+            && (self.cpu.idle() || self.cpu.jammed())
+            && !self.dma_controller.borrow().dma_in_progress
+    }
+
+    // logs the instruction the CPU is about to fetch, tracing stops if the
+    // sink fails
+    fn trace(&mut self) {
+        let Some(line) = self.cpu.trace() else {
+            return;
+        };
+        let (scanline, dot) = self.ppu.borrow().position();
+        let cycles = self.cycles / 3;
+        if let Some(sink) = self.trace_sink.as_mut() {
+            let result = writeln!(
+                sink,
+                "{} PPU:{:>3},{:>3} CYC:{}",
+                line, scanline, dot, cycles
+            );
+            if result.is_err() {
+                self.trace_sink = None;
+            }
+        }
+    }
 
-    fn connect_mapper(
+    fn restore(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.cpu.load_state(state)?;
+        self.ppu.borrow_mut().load_state(state)?;
+        self.apu.borrow_mut().load_state(state)?;
+        self.dma_controller.borrow_mut().load_state(state)?;
+        self.gamepad1.borrow_mut().load_state(state)?;
+        self.gamepad2.borrow_mut().load_state(state)?;
+        if let Some(mapper) = self.mapper.borrow_mut().as_mut() {
+            mapper.load_state(state)?;
+        }
+        self.cycles = state.read_usize()?;
+        Ok(())
+    }
+
+    pub fn cartridge_info(&self) -> Option<&CartridgeInfo> {
+        self.cartridge_info.as_ref()
+    }
+
+    /// CPU registers and cycle count, mid-instruction unless called at an
+    /// instruction boundary (e.g. right after `step_instruction`)
+    pub fn cpu_state(&self) -> CpuState {
+        self.cpu.state(self.cycles.div_ceil(3))
+    }
+
+    /// Reads a byte from the CPU address space as the CPU would, so reading
+    /// registers such as $2002 or $4016 has the usual side effects
+    pub fn read_memory(&mut self, address: u16) -> u8 {
+        self.cpu.bus.read(address)
+    }
+
+    /// Makes `load_rom` build cartridges with this mapper number (and
+    /// submapper, or any submapper if `None`) using `constructor`, in place of
+    /// the built-in implementation if there is one
+    pub fn register_mapper(
         &mut self,
-        (prg_mapper, chr_mapper): (impl Device + 'static, impl Device + 'static),
+        mapper_id: u16,
+        submapper_id: Option<u8>,
+        constructor: MapperConstructor,
     ) {
-        self.cpu.bus.connect(0x8000..=0xFFFF, prg_mapper);
-        self.ppu
-            .borrow_mut()
-            .bus
-            .connect(0x0000..=0x1FFF, chr_mapper);
+        self.mappers.register(mapper_id, submapper_id, constructor);
+    }
+
+    /// Logs every instruction to `sink` before it runs, in the format of
+    /// nestest.log: PC, opcode bytes, disassembly, A/X/Y/P/SP, PPU scanline
+    /// and dot and the CPU cycle count
+    pub fn set_trace_sink(&mut self, sink: Box<dyn Write>) {
+        self.trace_sink = Some(sink);
+    }
+
+    /// Stops the trace started by `set_trace_sink`, flushing the sink
+    pub fn clear_trace_sink(&mut self) {
+        if let Some(mut sink) = self.trace_sink.take() {
+            let _ = sink.flush();
+        }
     }
 }
 
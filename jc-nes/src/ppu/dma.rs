@@ -1,4 +1,5 @@
 use crate::bus::{Bus, Device};
+use crate::state::{StateError, StateReader, StateWriter};
 
 #[derive(Default)]
 pub struct OamDma {
@@ -12,7 +13,7 @@ pub struct OamDma {
 impl OamDma {
     pub fn transfer(&mut self, cur_cyc: usize, cpu_bus: &mut Bus) {
         if self.synched {
-            if cur_cyc % 2 == 0 {
+            if cur_cyc.is_multiple_of(2) {
                 // read byte from mem based on page
                 let address = (self.page as u16) << 8 | self.transfered as u16;
                 self.buffer = cpu_bus.read(address);
@@ -52,12 +53,29 @@ impl OamDma {
 
 // This interface is exposed for OAMDMA (address $4014 on CPU Bus)
 impl Device for OamDma {
+    // write-only
     fn read(&mut self, _address: u16) -> u8 {
-        eprintln!("can not read from OAMDMA ($4014)");
         0x00
     }
 
     fn write(&mut self, _address: u16, data: u8) {
         self.start(data);
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.dma_in_progress);
+        state.write_bool(self.synched);
+        state.write_u8(self.buffer);
+        state.write_u8(self.page);
+        state.write_u8(self.transfered);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.dma_in_progress = state.read_bool()?;
+        self.synched = state.read_bool()?;
+        self.buffer = state.read_u8()?;
+        self.page = state.read_u8()?;
+        self.transfered = state.read_u8()?;
+        Ok(())
+    }
 }
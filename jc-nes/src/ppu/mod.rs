@@ -1,4 +1,5 @@
 pub mod dma;
+pub mod nametables;
 pub mod palette;
 
 mod control;
@@ -14,8 +15,8 @@ use crate::ppu::palette::PALETTE;
 use crate::ppu::status::Status;
 use crate::ppu::vram_address::VRAMAddress;
 use crate::{
-    bus::{Bus, Device},
-    cartridge::MirrorMode,
+    bus::{Bus, Device, Fetch},
+    state::{StateError, StateReader, StateWriter},
 };
 
 pub const WIDTH: u16 = 256;
@@ -27,7 +28,6 @@ pub struct Ppu {
     pub(crate) raise_nmi: bool,
     pub(crate) bus: Bus,
     pub(crate) oam: Oam,
-    pub(crate) mirror_mode: MirrorMode,
 
     // current screen pixel
     cycle: u16,
@@ -91,7 +91,6 @@ impl Ppu {
             bg_shifter_pattern_hi: 0x0000,
             bg_shifter_attrib_lo: 0x0000,
             bg_shifter_attrib_hi: 0x0000,
-            mirror_mode: MirrorMode::Horizontal,
             oam: Oam::default(),
             scanline_sprites: Vec::with_capacity(8),
             sprite_shifter_pattern_lo: [0u8; 8],
@@ -123,17 +122,19 @@ impl Ppu {
                     0 => {
                         self.load_background_shifters();
 
-                        self.bg_next_tile_id = self
-                            .bus
-                            .read(0x2000 | (u16::from(self.vram_address) & 0x0FFF));
+                        self.bg_next_tile_id = self.bus.fetch(
+                            0x2000 | (u16::from(self.vram_address) & 0x0FFF),
+                            self.fetch_kind(false),
+                        );
                     }
                     2 => {
-                        self.bg_next_tile_attrib = self.bus.read(
+                        self.bg_next_tile_attrib = self.bus.fetch(
                             0x23C0
                                 | ((self.vram_address.nametable_y as u16) << 11)
                                 | ((self.vram_address.nametable_x as u16) << 10)
                                 | (((self.vram_address.coarse_y as u16) >> 2) << 3)
                                 | (self.vram_address.coarse_x as u16) >> 2,
+                            self.fetch_kind(false),
                         );
 
                         if (self.vram_address.coarse_y & 0x0002) != 0 {
@@ -147,18 +148,20 @@ impl Ppu {
                         self.bg_next_tile_attrib &= 0x03;
                     }
                     4 => {
-                        self.bg_next_tile_lsb = self.bus.read(
+                        self.bg_next_tile_lsb = self.bus.fetch(
                             ((self.control.pattern_background as u16) << 12)
                                 + ((self.bg_next_tile_id as u16) << 4)
                                 + self.vram_address.fine_y as u16,
+                            self.fetch_kind(false),
                         );
                     }
                     6 => {
-                        self.bg_next_tile_msb = self.bus.read(
+                        self.bg_next_tile_msb = self.bus.fetch(
                             ((self.control.pattern_background as u16) << 12)
                                 + ((self.bg_next_tile_id as u16) << 4)
                                 + self.vram_address.fine_y as u16
                                 + 8,
+                            self.fetch_kind(false),
                         );
                     }
                     7 => self.inc_x(),
@@ -176,9 +179,10 @@ impl Ppu {
             }
 
             if self.cycle == 338 || self.cycle == 340 {
-                self.bg_next_tile_id = self
-                    .bus
-                    .read(0x2000 | (u16::from(self.vram_address) & 0x0FFF));
+                self.bg_next_tile_id = self.bus.fetch(
+                    0x2000 | (u16::from(self.vram_address) & 0x0FFF),
+                    self.fetch_kind(false),
+                );
             }
 
             if self.scanline == -1 && self.cycle >= 280 && self.cycle < 305 {
@@ -321,7 +325,7 @@ impl Ppu {
         }
 
         // populate shifters with next scanline data
-        if self.cycle == 340 {
+        if self.cycle == 340 && self.scanline < HEIGHT as i16 && self.rendering() {
             for (i, sprite) in self.scanline_sprites.iter().enumerate() {
                 let sprite_pattern_addr_lo = if self.control.sprite_size {
                     // 8x16 mode
@@ -369,8 +373,9 @@ impl Ppu {
 
                 let sprite_pattern_addr_hi = sprite_pattern_addr_lo + 8;
 
-                let mut sprite_pattern_bits_lo = self.bus.read(sprite_pattern_addr_lo);
-                let mut sprite_pattern_bits_hi = self.bus.read(sprite_pattern_addr_hi);
+                let fetch = self.fetch_kind(true);
+                let mut sprite_pattern_bits_lo = self.bus.fetch(sprite_pattern_addr_lo, fetch);
+                let mut sprite_pattern_bits_hi = self.bus.fetch(sprite_pattern_addr_hi, fetch);
 
                 if sprite.attr & 0x40 != 0 {
                     sprite_pattern_bits_lo = sprite_pattern_bits_lo.reverse_bits();
@@ -380,9 +385,32 @@ impl Ppu {
                 self.sprite_shifter_pattern_lo[i] = sprite_pattern_bits_lo;
                 self.sprite_shifter_pattern_hi[i] = sprite_pattern_bits_hi;
             }
+
+            // unused sprite slots still fetch tile $FF, mappers watching A12 rely on it
+            let dummy_pattern_addr = if self.control.sprite_size {
+                0x1FF0
+            } else {
+                ((self.control.pattern_sprite as u16) << 12) | 0x0FF0
+            };
+            for _ in self.scanline_sprites.len()..8 {
+                self.bus.fetch(dummy_pattern_addr, self.fetch_kind(true));
+                self.bus
+                    .fetch(dummy_pattern_addr + 8, self.fetch_kind(true));
+            }
         }
     }
 
+    // scanline and dot about to be rendered, with the pre-render line as 261
+    // the way traces number it
+    pub(crate) fn position(&self) -> (u16, u16) {
+        let scanline = if self.scanline < 0 {
+            261
+        } else {
+            self.scanline as u16
+        };
+        (scanline, self.cycle)
+    }
+
     pub fn reset(&mut self) {
         self.cycle = 0;
         self.scanline = 0;
@@ -414,8 +442,19 @@ impl Ppu {
 }
 
 impl Ppu {
+    fn rendering(&self) -> bool {
+        self.mask.render_background || self.mask.render_sprites
+    }
+
+    fn fetch_kind(&self, sprite: bool) -> Fetch {
+        Fetch {
+            sprite,
+            tall_sprites: self.control.sprite_size,
+        }
+    }
+
     fn inc_x(&mut self) {
-        if self.mask.render_background || self.mask.render_sprites {
+        if self.rendering() {
             self.vram_address.coarse_x += 1;
             if self.vram_address.coarse_x == 32 {
                 self.vram_address.coarse_x = 0;
@@ -425,7 +464,7 @@ impl Ppu {
     }
 
     fn inc_y(&mut self) {
-        if self.mask.render_background || self.mask.render_sprites {
+        if self.rendering() {
             if self.vram_address.fine_y < 7 {
                 self.vram_address.fine_y += 1;
             } else {
@@ -443,14 +482,14 @@ impl Ppu {
     }
 
     fn reset_x(&mut self) {
-        if self.mask.render_background || self.mask.render_sprites {
+        if self.rendering() {
             self.vram_address.nametable_x = self.tram_address.nametable_x;
             self.vram_address.coarse_x = self.tram_address.coarse_x;
         }
     }
 
     fn reset_y(&mut self) {
-        if self.mask.render_background || self.mask.render_sprites {
+        if self.rendering() {
             self.vram_address.nametable_y = self.tram_address.nametable_y;
             self.vram_address.coarse_y = self.tram_address.coarse_y;
             self.vram_address.fine_y = self.tram_address.fine_y;
@@ -574,32 +613,79 @@ impl Device for Ppu {
                 let vram_address = u16::from(self.vram_address);
                 self.bus.write(vram_address, data);
 
-                if vram_address < 0x3F00 {
-                    let nametable_i = ((vram_address - 0x2000) / 0x400) % 4;
-                    match self.mirror_mode {
-                        //nametables: [A, A, B, B]
-                        MirrorMode::Horizontal => {
-                            if nametable_i == 0 || nametable_i == 2 {
-                                self.bus.write(vram_address + 0x400, data);
-                            } else if nametable_i == 1 || nametable_i == 3 {
-                                self.bus.write(vram_address - 0x400, data);
-                            };
-                        }
-                        //nametables: [A, B, A, B]
-                        MirrorMode::Vertical => {
-                            if nametable_i == 0 || nametable_i == 1 {
-                                self.bus.write(vram_address + 0x800, data);
-                            } else if nametable_i == 2 || nametable_i == 3 {
-                                self.bus.write(vram_address - 0x800, data);
-                            };
-                        }
-                    }
-                }
-
                 let increment = if self.control.increment_mode { 32 } else { 1 } as u16;
                 self.vram_address = (u16::from(self.vram_address) + increment).into();
             }
             _ => (),
         }
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.frame_complete);
+        state.write_bool(self.raise_nmi);
+        state.write_u8(self.oam.addr);
+        state.write_bytes(&self.oam.mem);
+        state.write_u16(self.cycle);
+        state.write_u16(self.scanline as u16);
+        state.write_u8(u8::from(self.status));
+        state.write_u8(u8::from(self.mask));
+        state.write_u8(u8::from(self.control));
+        state.write_u16(u16::from(self.vram_address));
+        state.write_u16(u16::from(self.tram_address));
+        state.write_u8(self.fine_x);
+        state.write_bool(self.write_flip_flop);
+        state.write_u8(self.buffer);
+        state.write_u8(self.bg_next_tile_id);
+        state.write_u8(self.bg_next_tile_attrib);
+        state.write_u8(self.bg_next_tile_lsb);
+        state.write_u8(self.bg_next_tile_msb);
+        state.write_u16(self.bg_shifter_pattern_lo);
+        state.write_u16(self.bg_shifter_pattern_hi);
+        state.write_u16(self.bg_shifter_attrib_lo);
+        state.write_u16(self.bg_shifter_attrib_hi);
+        let sprites: Vec<u8> = self
+            .scanline_sprites
+            .iter()
+            .flat_map(<[u8; 4]>::from)
+            .collect();
+        state.write_bytes(&sprites);
+        state.write_bytes(&self.sprite_shifter_pattern_lo);
+        state.write_bytes(&self.sprite_shifter_pattern_hi);
+        state.write_bool(self.sprite_zero_selected);
+        self.bus.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.frame_complete = state.read_bool()?;
+        self.raise_nmi = state.read_bool()?;
+        self.oam.addr = state.read_u8()?;
+        state.read_bytes_into(&mut self.oam.mem)?;
+        self.cycle = state.read_u16()?;
+        self.scanline = state.read_u16()? as i16;
+        self.status = Status::from(state.read_u8()?);
+        self.mask = Mask::from(state.read_u8()?);
+        self.control = Control::from(state.read_u8()?);
+        self.vram_address = VRAMAddress::from(state.read_u16()?);
+        self.tram_address = VRAMAddress::from(state.read_u16()?);
+        self.fine_x = state.read_u8()?;
+        self.write_flip_flop = state.read_bool()?;
+        self.buffer = state.read_u8()?;
+        self.bg_next_tile_id = state.read_u8()?;
+        self.bg_next_tile_attrib = state.read_u8()?;
+        self.bg_next_tile_lsb = state.read_u8()?;
+        self.bg_next_tile_msb = state.read_u8()?;
+        self.bg_shifter_pattern_lo = state.read_u16()?;
+        self.bg_shifter_pattern_hi = state.read_u16()?;
+        self.bg_shifter_attrib_lo = state.read_u16()?;
+        self.bg_shifter_attrib_hi = state.read_u16()?;
+        let sprites = state.read_bytes()?;
+        if sprites.len() > 8 * 4 || sprites.len() % 4 != 0 {
+            return Err(StateError::Corrupted);
+        }
+        self.scanline_sprites = sprites.chunks(4).map(Sprite::from).collect();
+        state.read_bytes_into(&mut self.sprite_shifter_pattern_lo)?;
+        state.read_bytes_into(&mut self.sprite_shifter_pattern_hi)?;
+        self.sprite_zero_selected = state.read_bool()?;
+        self.bus.load_state(state)
+    }
 }
@@ -0,0 +1,57 @@
+use crate::bus::{Device, SharedMut};
+use crate::cartridge::MirrorMode;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// https://wiki.nesdev.com/w/index.php/Mirroring#Nametable_Mirroring
+pub struct Nametables {
+    // 2 KiB of console CIRAM followed by the 2 KiB four-screen boards add
+    vram: [u8; 4 * 1024],
+    mirror_mode: SharedMut<MirrorMode>,
+}
+
+impl Nametables {
+    pub fn new(mirror_mode: SharedMut<MirrorMode>) -> Nametables {
+        Nametables {
+            vram: [0u8; 4 * 1024],
+            mirror_mode,
+        }
+    }
+
+    // maps one of the four logical nametables to a physical 1 KiB page
+    fn map_address(&self, address: u16) -> usize {
+        let nametable = (address / 0x0400) % 4;
+        let page = match *self.mirror_mode.borrow() {
+            //nametables: [A, A, B, B]
+            MirrorMode::Horizontal => nametable / 2,
+            //nametables: [A, B, A, B]
+            MirrorMode::Vertical => nametable % 2,
+            //nametables: [A, A, A, A]
+            MirrorMode::SingleScreenLower => 0,
+            //nametables: [B, B, B, B]
+            MirrorMode::SingleScreenUpper => 1,
+            //nametables: [A, B, C, D]
+            MirrorMode::FourScreen => nametable,
+        };
+        page as usize * 0x0400 + (address & 0x03FF) as usize
+    }
+}
+
+// This interface is exposed for the nametables ($2000-$2FFF on PPU Bus)
+impl Device for Nametables {
+    fn read(&mut self, address: u16) -> u8 {
+        self.vram[self.map_address(address)]
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        let address = self.map_address(address);
+        self.vram[address] = data;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.vram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.vram)
+    }
+}
@@ -29,3 +29,9 @@ impl From<&[u8]> for Sprite {
         }
     }
 }
+
+impl From<&Sprite> for [u8; 4] {
+    fn from(sprite: &Sprite) -> [u8; 4] {
+        [sprite.y, sprite.tile_id, sprite.attr, sprite.x]
+    }
+}
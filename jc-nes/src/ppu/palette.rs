@@ -1,4 +1,5 @@
 use crate::bus::Device;
+use crate::state::{StateError, StateReader, StateWriter};
 
 pub struct Palette {
     mem: [u8; 256],
@@ -31,6 +32,14 @@ impl Device for Palette {
         let address = self.mirror(address);
         self.mem[address] = data;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.mem);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.mem)
+    }
 }
 
 pub const PALETTE: [(u8, u8, u8); 0x40] = [
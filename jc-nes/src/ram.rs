@@ -1,4 +1,5 @@
 use crate::bus::Device;
+use crate::state::{StateError, StateReader, StateWriter};
 
 pub struct Ram {
     mem: Vec<u8>,
@@ -18,4 +19,12 @@ impl Device for Ram {
     fn write(&mut self, address: u16, data: u8) {
         self.mem[address as usize] = data;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.mem);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.mem)
+    }
 }
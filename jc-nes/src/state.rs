@@ -0,0 +1,198 @@
+use std::error::Error;
+use std::fmt;
+
+#[cfg(feature = "web")]
+use wasm_bindgen::prelude::*;
+
+const MAGIC: [u8; 4] = *b"JCNS";
+
+// bump whenever the layout of any saved component changes, including new
+// devices, new mappers and new values of an existing field
+pub const VERSION: u32 = 6;
+
+/// Reasons a save state can be rejected by `Nes::load_state`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with the save state signature
+    BadMagic,
+    /// The state was written by a different (incompatible) version
+    UnsupportedVersion(u32),
+    /// The state was saved while running a different ROM
+    RomMismatch,
+    /// The data ends before the state is complete
+    Truncated,
+    /// The data does not describe a valid machine state
+    Corrupted,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state (signature not correct)"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported save state version {} (expected {})",
+                version, VERSION
+            ),
+            StateError::RomMismatch => write!(f, "save state belongs to a different ROM"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Corrupted => write!(f, "save state is corrupted"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+#[cfg(feature = "web")]
+impl From<StateError> for JsValue {
+    fn from(error: StateError) -> JsValue {
+        JsError::new(&error.to_string()).into()
+    }
+}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new(rom_hash: u64) -> StateWriter {
+        let mut writer = StateWriter { data: Vec::new() };
+        writer.data.extend_from_slice(&MAGIC);
+        writer.write_u32(VERSION);
+        writer.write_u64(rom_hash);
+        writer
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8], rom_hash: u64) -> Result<StateReader<'a>, StateError> {
+        if data.get(0..4) != Some(&MAGIC[..]) {
+            return Err(StateError::BadMagic);
+        }
+
+        let mut reader = StateReader { data, position: 4 };
+        let version = reader.read_u32()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if reader.read_u64()? != rom_hash {
+            return Err(StateError::RomMismatch);
+        }
+        Ok(reader)
+    }
+
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.position == self.data.len() {
+            Ok(())
+        } else {
+            Err(StateError::Corrupted)
+        }
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupted),
+        }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let mut bytes = [0u8; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize, StateError> {
+        Ok(self.read_u64()? as usize)
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+
+    /// Reads a byte block into a buffer that must have the exact same length
+    pub fn read_bytes_into(&mut self, buffer: &mut [u8]) -> Result<(), StateError> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != buffer.len() {
+            return Err(StateError::Corrupted);
+        }
+        buffer.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .ok_or(StateError::Truncated)?;
+        let bytes = self
+            .data
+            .get(self.position..end)
+            .ok_or(StateError::Truncated)?;
+        self.position = end;
+        Ok(bytes)
+    }
+}
+
+// FNV-1a, identifies the ROM a state was saved with
+pub fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
@@ -0,0 +1,149 @@
+// Cycle counts and bus accesses of single instructions, seen through a mapper
+// that logs every CPU access to the cartridge
+use jc_nes::{Cartridge, LoadError, Mapper, Nes};
+use std::cell::RefCell;
+
+#[derive(Debug, PartialEq)]
+enum Access {
+    Read(u16),
+    Write(u16, u8),
+}
+
+thread_local! {
+    static ACCESSES: RefCell<Vec<Access>> = const { RefCell::new(Vec::new()) };
+}
+
+// 32 KiB of PRG-ROM at $8000 and nametable RAM, nothing else
+struct Logger {
+    prg: Vec<u8>,
+    vram: Vec<u8>,
+}
+
+impl Mapper for Logger {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        ACCESSES.with(|accesses| accesses.borrow_mut().push(Access::Read(address)));
+        match address {
+            0x8000..=0xFFFF => self.prg[(address - 0x8000) as usize],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        ACCESSES.with(|accesses| accesses.borrow_mut().push(Access::Write(address, data)));
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x2000..=0x2FFF => self.vram[(address - 0x2000) as usize],
+            _ => 0,
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        if let 0x2000..=0x2FFF = address {
+            self.vram[(address - 0x2000) as usize] = data;
+        }
+    }
+}
+
+fn logger(cartridge: Cartridge) -> Result<Box<dyn Mapper>, LoadError> {
+    Ok(Box::new(Logger {
+        prg: cartridge.prg_rom().to_vec(),
+        vram: vec![0; 0x1000],
+    }))
+}
+
+// runs `program` from $8000, with `data` placed at $9000
+fn nes(program: &[u8], data: u8) -> Nes {
+    let mut prg = vec![0xEA; 0x8000];
+    prg[..program.len()].copy_from_slice(program);
+    prg[0x1000] = data;
+    prg[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    // iNES, mapper 0 with 8 KiB of CHR-RAM
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 2, 0, 0, 0];
+    rom.extend([0x00; 8]);
+    rom.extend(prg);
+
+    let mut nes = Nes::new();
+    nes.register_mapper(0, None, logger);
+    nes.load_rom(&rom).unwrap();
+    nes.reset();
+    // the reset sequence
+    nes.step_instruction();
+    nes
+}
+
+// (CPU cycles, cartridge accesses) of the next instruction
+fn step(nes: &mut Nes) -> (usize, Vec<Access>) {
+    ACCESSES.with(|accesses| accesses.borrow_mut().clear());
+    let cycles = nes.step_instruction() / 3;
+    (cycles, ACCESSES.with(|accesses| accesses.take()))
+}
+
+// https://www.nesdev.org/6502_cpu.txt, "Absolute indexed addressing"
+#[test]
+fn absolute_indexed_read_crossing_a_page() {
+    #[rustfmt::skip]
+    let mut nes = nes(&[
+        0xA2, 0x20,       // LDX #$20
+        0xBD, 0x10, 0x80, // LDA $8010,X
+        0xBD, 0xF0, 0x80, // LDA $80F0,X
+    ], 0x00);
+
+    assert_eq!(
+        step(&mut nes),
+        (2, vec![Access::Read(0x8000), Access::Read(0x8001)])
+    );
+    assert_eq!(
+        step(&mut nes),
+        (
+            4,
+            vec![
+                Access::Read(0x8002),
+                Access::Read(0x8003),
+                Access::Read(0x8004),
+                Access::Read(0x8030),
+            ]
+        )
+    );
+    // the high byte is fixed up a cycle late, after a read from the wrong page
+    assert_eq!(
+        step(&mut nes),
+        (
+            5,
+            vec![
+                Access::Read(0x8005),
+                Access::Read(0x8006),
+                Access::Read(0x8007),
+                Access::Read(0x8010),
+                Access::Read(0x8110),
+            ]
+        )
+    );
+}
+
+// https://www.nesdev.org/6502_cpu.txt, "Read-Modify-Write instructions"
+#[test]
+fn read_modify_write_writes_twice() {
+    #[rustfmt::skip]
+    let mut nes = nes(&[
+        0xEE, 0x00, 0x90, // INC $9000
+    ], 0x41);
+
+    // the unmodified value goes back out while the ALU works on it
+    assert_eq!(
+        step(&mut nes),
+        (
+            6,
+            vec![
+                Access::Read(0x8000),
+                Access::Read(0x8001),
+                Access::Read(0x8002),
+                Access::Read(0x9000),
+                Access::Write(0x9000, 0x41),
+                Access::Write(0x9000, 0x42),
+            ]
+        )
+    );
+}
@@ -0,0 +1,170 @@
+// Cartridge loading and mapper behaviour that shows up through the CPU,
+// checked with small hand-assembled ROMs
+use jc_nes::{CartridgeInfo, LoadError, Nes};
+use std::convert::TryFrom;
+
+// iNES image with `prg` as PRG-ROM and 8 KiB of CHR-RAM
+fn rom(mapper_id: u8, prg: Vec<u8>) -> Vec<u8> {
+    let prg_banks = (prg.len() / 0x4000) as u8;
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, prg_banks, 0, mapper_id << 4, 0];
+    rom.extend([0x00; 8]);
+    rom.extend(prg);
+    rom
+}
+
+// MMC1 ignores the second write of a read-modify-write instruction, so INC on
+// a $FF byte only resets the shift register (Bill & Ted's Excellent Adventure
+// relies on this)
+#[test]
+fn mmc1_ignores_consecutive_writes() {
+    // two 16 KiB banks, the last one fixed at $C000
+    let mut prg = vec![0xEA; 0x8000];
+    prg[0x1000] = 0x11;
+    prg[0x5000] = 0x22;
+    prg[0x7FF0] = 0xFF;
+
+    #[rustfmt::skip]
+    let program = [
+        0xEE, 0xF0, 0xFF, // INC $FFF0 (writes $FF, then $00)
+        0xA9, 0x01,       // LDA #$01
+        0x8D, 0x00, 0xE0, // STA $E000 (PRG bank, one bit per write)
+        0x4A,             // LSR A
+        0x8D, 0x00, 0xE0, // STA $E000
+        0x8D, 0x00, 0xE0, // STA $E000
+        0x8D, 0x00, 0xE0, // STA $E000
+        0x8D, 0x00, 0xE0, // STA $E000
+        0x4C, 0x16, 0xC0, // JMP $C016
+    ];
+    prg[0x4000..0x4000 + program.len()].copy_from_slice(&program);
+    prg[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0xC0]);
+
+    let mut nes = Nes::new();
+    nes.load_rom(&rom(1, prg)).unwrap();
+    nes.reset();
+    nes.run_frame();
+
+    // bank 1 at $8000 means the five writes loaded the register on their own
+    assert_eq!(nes.read_memory(0x9000), 0x22);
+}
+
+// AxROM shows one nametable on all four screens, picked by bit 4 of the bank
+// register
+#[test]
+fn axrom_selects_single_screen_nametable() {
+    let mut prg = vec![0xEA; 0x8000];
+    // bank register writes go to a $FF byte, which is safe with bus conflicts
+    prg[0x7FF0] = 0xFF;
+
+    #[rustfmt::skip]
+    let mut program = vec![
+        0x2C, 0x02, 0x20, // BIT $2002 (wait for the PPU)
+        0x10, 0xFB,       // BPL to the BIT
+        0xA9, 0x20,       // LDA #$20
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x00,       // LDA #$00
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0xAA,       // LDA #$AA
+        0x8D, 0x07, 0x20, // STA $2007 ($2000 on the lower nametable)
+    ];
+    // reads `address` through $2007 into `result` after selecting `page`
+    let mut read_nametable = |page: u8, address: u16, result: u8| {
+        let [hi, lo] = address.to_be_bytes();
+        #[rustfmt::skip]
+        program.extend([
+            0xA9, page,       // LDA #page
+            0x8D, 0xF0, 0xFF, // STA $FFF0
+            0xA9, hi,         // LDA #hi
+            0x8D, 0x06, 0x20, // STA $2006
+            0xA9, lo,         // LDA #lo
+            0x8D, 0x06, 0x20, // STA $2006
+            0xAD, 0x07, 0x20, // LDA $2007 (fills the read buffer)
+            0xAD, 0x07, 0x20, // LDA $2007
+            0x85, result,     // STA result
+        ]);
+    };
+    read_nametable(0x10, 0x2000, 0x00);
+    read_nametable(0x00, 0x2400, 0x01);
+    let [lo, hi] = (0x8000 + program.len() as u16).to_le_bytes();
+    program.extend([0x4C, lo, hi]);
+
+    prg[..program.len()].copy_from_slice(&program);
+    prg[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    let mut nes = Nes::new();
+    nes.load_rom(&rom(7, prg)).unwrap();
+    nes.reset();
+    nes.run_frame();
+    nes.run_frame();
+
+    // the upper nametable is still empty, the lower one shows up at $2400 too
+    assert_eq!(nes.read_memory(0x0000), 0x00);
+    assert_eq!(nes.read_memory(0x0001), 0xAA);
+}
+
+// NES 2.0 exponent notation can describe PRG-ROM smaller than the 16 KiB the
+// mappers switch in
+#[test]
+fn partial_prg_bank_is_rejected() {
+    // 2^13 * 1 = 8 KiB
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 13 << 2, 0, 0x00, 0x08, 0, 0x0F];
+    rom.extend([0x00; 6]);
+    rom.extend(vec![0xEA; 0x2000]);
+
+    let mut nes = Nes::new();
+    assert_eq!(
+        nes.load_rom(&rom),
+        Err(LoadError::UnsupportedFormat(
+            "PRG-ROM of 8192 bytes (not a multiple of 16 KiB)".to_string()
+        ))
+    );
+}
+
+// NES 2.0 headers declare the CHR-RAM size, less than the 8 KiB pattern table
+// window is mirrored across it
+#[test]
+fn declared_chr_ram_size_is_used() {
+    let mut prg = vec![0xEA; 0x4000];
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x00,       // LDA #$00
+        0x8D, 0x06, 0x20, // STA $2006
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x5A,       // LDA #$5A
+        0x8D, 0x07, 0x20, // STA $2007 (CHR-RAM $0000)
+        0xA9, 0x10,       // LDA #$10
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x00,       // LDA #$00
+        0x8D, 0x06, 0x20, // STA $2006
+        0xAD, 0x07, 0x20, // LDA $2007 (fills the read buffer)
+        0xAD, 0x07, 0x20, // LDA $2007 (CHR-RAM $1000)
+        0x85, 0x00,       // STA $00
+        0x4C, 0x1F, 0xC0, // JMP $C01F
+    ];
+    prg[..program.len()].copy_from_slice(&program);
+    prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+
+    // 4 KiB of CHR-RAM (64 << 6)
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 0, 0x00, 0x08, 0, 0, 0, 0x06];
+    rom.extend([0x00; 4]);
+    rom.extend(prg);
+
+    let mut nes = Nes::new();
+    nes.load_rom(&rom).unwrap();
+    nes.reset();
+    nes.run_frame();
+
+    assert_eq!(nes.read_memory(0x0000), 0x5A);
+}
+
+#[test]
+fn short_header_is_rejected() {
+    let header = [0x4E, 0x45, 0x53, 0x1A, 1, 0, 0x00, 0x08];
+    assert_eq!(
+        CartridgeInfo::try_from(&header[..]),
+        Err(LoadError::TruncatedHeader)
+    );
+    assert_eq!(
+        Nes::new().load_rom(&header),
+        Err(LoadError::TruncatedHeader)
+    );
+}
@@ -0,0 +1,256 @@
+// Runs the community test ROMs under tests/roms (see the README there). They
+// aren't committed, so those tests are ignored unless asked for with
+// `cargo test -- --ignored`
+use jc_nes::{CpuState, Nes};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// blargg ROMs report through PRG-RAM once this signature is at $6001
+// https://github.com/christopherpow/nes-test-roms/blob/master/instr_test-v5/readme.txt
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const RUNNING: u8 = 0x80;
+const NEEDS_RESET: u8 = 0x81;
+
+// emulated time before a ROM is considered stuck
+const MAX_FRAMES: usize = 60 * 60;
+
+fn read_rom(path: &str) -> Vec<u8> {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "roms", path]
+        .iter()
+        .collect();
+    fs::read(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err))
+}
+
+fn blargg(path: &str) {
+    run_blargg(path, &read_rom(path));
+}
+
+fn run_blargg(name: &str, rom: &[u8]) {
+    let mut nes = Nes::new();
+    nes.load_rom(rom).unwrap();
+    nes.reset();
+
+    let mut reset_frame = None;
+    for frame in 0..MAX_FRAMES {
+        nes.run_frame();
+        if (0..3).any(|i| nes.read_memory(0x6001 + i) != SIGNATURE[i as usize]) {
+            continue;
+        }
+
+        match nes.read_memory(0x6000) {
+            RUNNING => (),
+            // the reset has to come at least 100 ms after the request
+            NEEDS_RESET => {
+                if frame >= *reset_frame.get_or_insert(frame + 6) {
+                    nes.reset();
+                    reset_frame = None;
+                }
+            }
+            code => {
+                let text = read_text(&mut nes);
+                assert_eq!(code, 0, "{} failed with code {}:\n{}", name, code, text);
+                return;
+            }
+        }
+    }
+    panic!("{} did not finish in {} frames", name, MAX_FRAMES);
+}
+
+// null-terminated text output at $6004
+fn read_text(nes: &mut Nes) -> String {
+    let text: Vec<u8> = (0x6004..0x7000)
+        .map(|address| nes.read_memory(address))
+        .take_while(|&byte| byte != 0x00)
+        .collect();
+    String::from_utf8_lossy(&text).into_owned()
+}
+
+// nestest.log lines look like
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+fn parse_log_line(line: &str) -> CpuState {
+    let field = |name: &str| {
+        let start = line.find(name).unwrap() + name.len();
+        line[start..].split_whitespace().next().unwrap()
+    };
+    let hex = |name: &str| u8::from_str_radix(field(name), 16).unwrap();
+    CpuState {
+        pc: u16::from_str_radix(&line[0..4], 16).unwrap(),
+        a: hex("A:"),
+        x: hex("X:"),
+        y: hex("Y:"),
+        p: hex("P:"),
+        sp: hex("SP:"),
+        cycles: field("CYC:").parse().unwrap(),
+    }
+}
+
+#[test]
+#[ignore = "needs the test ROMs in tests/roms, see tests/roms/README.md"]
+fn nestest() {
+    let mut rom = read_rom("nestest/nestest.nes");
+    let log = read_rom("nestest/nestest.log");
+
+    // automation mode starts at $C000 instead of the reset vector
+    let vector = 16 + 0x3FFC;
+    rom[vector..vector + 2].copy_from_slice(&[0x00, 0xC0]);
+
+    let mut nes = Nes::new();
+    nes.load_rom(&rom).unwrap();
+    nes.reset();
+    nes.step_instruction();
+
+    let log = String::from_utf8(log).unwrap();
+    for (number, line) in log.lines().enumerate() {
+        let expected = parse_log_line(line);
+        assert_eq!(
+            nes.cpu_state(),
+            expected,
+            "nestest.log:{}\n{}",
+            number + 1,
+            line
+        );
+        nes.step_instruction();
+    }
+
+    // error codes of the official and unofficial opcode tests
+    assert_eq!(nes.read_memory(0x0002), 0x00);
+    assert_eq!(nes.read_memory(0x0003), 0x00);
+}
+
+// NROM image that reports `code` and `text` the way blargg ROMs do, to check
+// the harness itself without any vendored ROMs
+fn protocol_rom(code: u8, text: &str) -> Vec<u8> {
+    let mut program = Vec::new();
+    let mut store = |address: u16, value: u8| {
+        // LDA #value, STA address
+        let [lo, hi] = address.to_le_bytes();
+        program.extend([0xA9, value, 0x8D, lo, hi]);
+    };
+    store(0x6000, RUNNING);
+    for (i, &byte) in SIGNATURE.iter().enumerate() {
+        store(0x6001 + i as u16, byte);
+    }
+    for (i, byte) in text.bytes().chain([0x00]).enumerate() {
+        store(0x6004 + i as u16, byte);
+    }
+    store(0x6000, code);
+    // JMP to itself
+    let [lo, hi] = (0x8000 + program.len() as u16).to_le_bytes();
+    program.extend([0x4C, lo, hi]);
+
+    let mut prg = vec![0xEA; 32 * 1024];
+    prg[..program.len()].copy_from_slice(&program);
+    prg[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend(prg);
+    rom.extend(vec![0x00; 8 * 1024]);
+    rom
+}
+
+#[test]
+fn blargg_protocol_pass() {
+    run_blargg("pass", &protocol_rom(0x00, "Passed"));
+}
+
+#[test]
+#[should_panic(expected = "failed with code 3:\nsome check")]
+fn blargg_protocol_fail() {
+    run_blargg("fail", &protocol_rom(0x03, "some check"));
+}
+
+// collects the trace where the test can still read it
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn trace() {
+    let mut nes = Nes::new();
+    nes.load_rom(&protocol_rom(0x00, "")).unwrap();
+    nes.reset();
+    let buffer = SharedBuffer::default();
+    nes.set_trace_sink(Box::new(buffer.clone()));
+    // the reset sequence isn't an instruction and isn't logged
+    for _ in 0..4 {
+        nes.step_instruction();
+    }
+    nes.clear_trace_sink();
+    nes.step_instruction();
+
+    let trace = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with(
+        "8000  A9 80     LDA #$80                        A:00 X:00 Y:00 P:24 SP:FD PPU:"
+    ));
+    assert!(lines[0].ends_with(" CYC:7"));
+    assert!(lines[1].starts_with(
+        "8002  8D 00 60  STA $6000 = 00                  A:80 X:00 Y:00 P:A4 SP:FD PPU:"
+    ));
+    assert!(lines[2].ends_with(" CYC:13"));
+}
+
+macro_rules! blargg_tests {
+    ($($name:ident: $path:expr,)*) => {
+        $(
+            #[test]
+            #[ignore = "needs the test ROMs in tests/roms, see tests/roms/README.md"]
+            fn $name() {
+                blargg($path);
+            }
+        )*
+    };
+}
+
+blargg_tests! {
+    instr_test_basics: "instr_test-v5/rom_singles/01-basics.nes",
+    instr_test_implied: "instr_test-v5/rom_singles/02-implied.nes",
+    instr_test_immediate: "instr_test-v5/rom_singles/03-immediate.nes",
+    instr_test_zero_page: "instr_test-v5/rom_singles/04-zero_page.nes",
+    instr_test_zp_xy: "instr_test-v5/rom_singles/05-zp_xy.nes",
+    instr_test_absolute: "instr_test-v5/rom_singles/06-absolute.nes",
+    instr_test_abs_xy: "instr_test-v5/rom_singles/07-abs_xy.nes",
+    instr_test_ind_x: "instr_test-v5/rom_singles/08-ind_x.nes",
+    instr_test_ind_y: "instr_test-v5/rom_singles/09-ind_y.nes",
+    instr_test_branches: "instr_test-v5/rom_singles/10-branches.nes",
+    instr_test_stack: "instr_test-v5/rom_singles/11-stack.nes",
+    instr_test_jmp_jsr: "instr_test-v5/rom_singles/12-jmp_jsr.nes",
+    instr_test_rts: "instr_test-v5/rom_singles/13-rts.nes",
+    instr_test_rti: "instr_test-v5/rom_singles/14-rti.nes",
+    instr_test_brk: "instr_test-v5/rom_singles/15-brk.nes",
+    instr_test_special: "instr_test-v5/rom_singles/16-special.nes",
+
+    ppu_vbl_nmi_vbl_basics: "ppu_vbl_nmi/rom_singles/01-vbl_basics.nes",
+    ppu_vbl_nmi_vbl_set_time: "ppu_vbl_nmi/rom_singles/02-vbl_set_time.nes",
+    ppu_vbl_nmi_vbl_clear_time: "ppu_vbl_nmi/rom_singles/03-vbl_clear_time.nes",
+    ppu_vbl_nmi_nmi_control: "ppu_vbl_nmi/rom_singles/04-nmi_control.nes",
+    ppu_vbl_nmi_nmi_timing: "ppu_vbl_nmi/rom_singles/05-nmi_timing.nes",
+    ppu_vbl_nmi_suppression: "ppu_vbl_nmi/rom_singles/06-suppression.nes",
+    ppu_vbl_nmi_nmi_on_timing: "ppu_vbl_nmi/rom_singles/07-nmi_on_timing.nes",
+    ppu_vbl_nmi_nmi_off_timing: "ppu_vbl_nmi/rom_singles/08-nmi_off_timing.nes",
+    ppu_vbl_nmi_even_odd_frames: "ppu_vbl_nmi/rom_singles/09-even_odd_frames.nes",
+    ppu_vbl_nmi_even_odd_timing: "ppu_vbl_nmi/rom_singles/10-even_odd_timing.nes",
+
+    apu_test_len_ctr: "apu_test/rom_singles/1-len_ctr.nes",
+    apu_test_len_table: "apu_test/rom_singles/2-len_table.nes",
+    apu_test_irq_flag: "apu_test/rom_singles/3-irq_flag.nes",
+    apu_test_jitter: "apu_test/rom_singles/4-jitter.nes",
+    apu_test_len_timing: "apu_test/rom_singles/5-len_timing.nes",
+    apu_test_irq_flag_timing: "apu_test/rom_singles/6-irq_flag_timing.nes",
+    apu_test_dmc_basics: "apu_test/rom_singles/7-dmc_basics.nes",
+    apu_test_dmc_rates: "apu_test/rom_singles/8-dmc_rates.nes",
+}
@@ -0,0 +1,15 @@
+#!/bin/sh
+# Copies the test ROMs from nes-test-roms into this directory
+set -e
+
+cd "$(dirname "$0")"
+source=$(mktemp -d)
+trap 'rm -rf "$source"' EXIT
+
+git clone --quiet --depth 1 https://github.com/christopherpow/nes-test-roms "$source"
+
+mkdir -p nestest instr_test-v5 ppu_vbl_nmi apu_test
+cp "$source/other/nestest.nes" "$source/other/nestest.log" nestest/
+cp -r "$source/instr_test-v5/rom_singles" instr_test-v5/
+cp -r "$source/ppu_vbl_nmi/rom_singles" ppu_vbl_nmi/
+cp -r "$source/apu_test/rom_singles" apu_test/
@@ -0,0 +1,136 @@
+// Save states restore the whole machine, and only into the build and ROM they
+// were written by
+use jc_nes::{Button, CpuState, Nes, StateError};
+
+const DONKEY_KONG: &[u8] = include_bytes!("../../jc-nes-web/site/public/roms/Donkey Kong.nes");
+
+// every built-in mapper
+const MAPPERS: [u16; 15] = [0, 1, 2, 3, 4, 5, 7, 9, 10, 11, 19, 24, 26, 66, 69];
+
+// NOPs, with both pulse channels turned up at the vectors ($EAEA) of every bank
+fn rom(mapper_id: u16) -> Vec<u8> {
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x03,       // LDA #$03
+        0x8D, 0x15, 0x40, // STA $4015
+        0xA9, 0xDF,       // LDA #$DF (constant volume 15)
+        0x8D, 0x00, 0x40, // STA $4000
+        0x8D, 0x04, 0x40, // STA $4004
+        0xA9, 0x40,       // LDA #$40
+        0x8D, 0x02, 0x40, // STA $4002
+        0x8D, 0x06, 0x40, // STA $4006
+        0xA9, 0x08,       // LDA #$08
+        0x8D, 0x03, 0x40, // STA $4003
+        0x8D, 0x07, 0x40, // STA $4007
+    ];
+    let mut prg = vec![0xEA; 8 * 0x4000];
+    for bank in prg.chunks_mut(0x4000) {
+        bank[0x2AEA..0x2AEA + program.len()].copy_from_slice(&program);
+    }
+
+    let flags6 = ((mapper_id & 0x0F) << 4) as u8;
+    let flags7 = (mapper_id & 0xF0) as u8;
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 8, 16, flags6, flags7];
+    rom.extend([0x00; 8]);
+    rom.extend(prg);
+    rom.extend(vec![0x00; 16 * 0x2000]);
+    rom
+}
+
+// what a few frames from now look like, the screen and the CPU
+fn run(nes: &mut Nes, frames: usize) -> (Vec<u8>, CpuState) {
+    for frame in 0..frames {
+        // something for the state to have to get right
+        if frame == frames / 2 {
+            nes.btn_down(1, Button::Start);
+        }
+        nes.run_frame();
+    }
+    nes.btn_up(1, Button::Start);
+    let screen = nes.get_frame().unwrap().to_vec();
+    (screen, nes.cpu_state())
+}
+
+// loading a state and running on gives the same result as running on from
+// where it was saved
+fn assert_round_trip(rom: &[u8], frames: usize) {
+    let mut nes = Nes::new();
+    nes.load_rom(rom).unwrap();
+    nes.reset();
+    nes.run_frame();
+
+    let state = nes.save_state();
+    let (expected_screen, expected_cpu) = run(&mut nes, frames);
+    nes.load_state(&state).unwrap();
+    let (screen, cpu) = run(&mut nes, frames);
+    assert_eq!(cpu, expected_cpu);
+    assert!(screen == expected_screen, "the screens differ");
+}
+
+#[test]
+fn state_round_trip() {
+    assert_round_trip(DONKEY_KONG, 30);
+}
+
+#[test]
+fn state_round_trip_for_every_mapper() {
+    for mapper_id in MAPPERS {
+        assert_round_trip(&rom(mapper_id), 4);
+    }
+}
+
+#[test]
+fn state_of_other_version_is_rejected() {
+    let mut nes = Nes::new();
+    nes.load_rom(DONKEY_KONG).unwrap();
+    nes.reset();
+    nes.run_frame();
+
+    let mut state = nes.save_state();
+    let version = u32::from_le_bytes([state[4], state[5], state[6], state[7]]);
+    state[4..8].copy_from_slice(&(version - 1).to_le_bytes());
+    assert_eq!(
+        nes.load_state(&state),
+        Err(StateError::UnsupportedVersion(version - 1))
+    );
+}
+
+#[test]
+fn state_of_other_rom_is_rejected() {
+    let mut nes = Nes::new();
+    nes.load_rom(DONKEY_KONG).unwrap();
+    nes.reset();
+    nes.run_frame();
+    let state = nes.save_state();
+
+    nes.load_rom(&rom(0)).unwrap();
+    assert_eq!(nes.load_state(&state), Err(StateError::RomMismatch));
+}
+
+// A state that is cut short or has a damaged byte has to be rejected or
+// loaded as something that can run, never panic later on
+#[test]
+fn damaged_state_is_rejected_or_runs() {
+    for mapper_id in [2, 7, 11, 66] {
+        let mut nes = Nes::new();
+        nes.load_rom(&rom(mapper_id)).unwrap();
+        nes.reset();
+        nes.run_frame();
+        let state = nes.save_state();
+
+        // every few lengths is enough, nothing is read past the end
+        for length in (0..state.len()).step_by(13) {
+            assert!(nes.load_state(&state[..length]).is_err());
+        }
+
+        // past the magic, version and ROM hash
+        for offset in 16..state.len() {
+            let mut damaged = state.clone();
+            damaged[offset] ^= 0xFF;
+            if nes.load_state(&damaged).is_ok() {
+                nes.step_instruction();
+                nes.step_instruction();
+            }
+        }
+    }
+}